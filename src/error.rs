@@ -0,0 +1,109 @@
+use js_sys::Reflect;
+use wasm_bindgen::JsValue;
+
+// 抛给 JS 的错误为带 code 字段的 Error 对象 { code, message }，便于前端按类型分支处理
+#[derive(Debug)]
+pub enum CprError {
+    InvalidConfig(String),           // 配置缺失或取值非法
+    InvalidInputFormat(String),      // 无法识别的输入格式
+    DecodeFailed(String),            // 输入图像、水印或字体解码失败
+    InputTooLarge(String),           // 输入字节数或像素数超出上限
+    CropOutOfBounds(String),         // 裁剪区域超出图像范围
+    WatermarkOutOfBounds(String),    // 水印位置超出图像范围
+    InvalidOpacity(String),          // 水印透明度超出范围
+    UnsupportedOutputFormat(String), // 不支持的输出格式
+    EncodeFailed(String),            // 编码失败
+    Timeout(String),                 // 处理超过 timeout_ms
+    Internal(String),                // 与 JS 交互等内部错误
+}
+
+impl CprError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            CprError::InvalidConfig(_) => "INVALID_CONFIG",
+            CprError::InvalidInputFormat(_) => "INVALID_INPUT_FORMAT",
+            CprError::DecodeFailed(_) => "DECODE_FAILED",
+            CprError::InputTooLarge(_) => "INPUT_TOO_LARGE",
+            CprError::CropOutOfBounds(_) => "CROP_OUT_OF_BOUNDS",
+            CprError::WatermarkOutOfBounds(_) => "WATERMARK_OUT_OF_BOUNDS",
+            CprError::InvalidOpacity(_) => "INVALID_OPACITY",
+            CprError::UnsupportedOutputFormat(_) => "UNSUPPORTED_OUTPUT_FORMAT",
+            CprError::EncodeFailed(_) => "ENCODE_FAILED",
+            CprError::Timeout(_) => "TIMEOUT",
+            CprError::Internal(_) => "INTERNAL_ERROR",
+        }
+    }
+
+    // 解码输入、水印等图像时的错误：超出解码器限制视为输入过大，其余均为解码失败
+    pub fn decode(context: &str, err: image::ImageError) -> Self {
+        match err {
+            image::ImageError::Limits(_) => {
+                CprError::InputTooLarge(format!("{}: {}", context, err))
+            }
+            _ => CprError::DecodeFailed(format!("{}: {}", context, err)),
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            CprError::InvalidConfig(message)
+            | CprError::InvalidInputFormat(message)
+            | CprError::DecodeFailed(message)
+            | CprError::InputTooLarge(message)
+            | CprError::CropOutOfBounds(message)
+            | CprError::WatermarkOutOfBounds(message)
+            | CprError::InvalidOpacity(message)
+            | CprError::UnsupportedOutputFormat(message)
+            | CprError::EncodeFailed(message)
+            | CprError::Timeout(message)
+            | CprError::Internal(message) => message,
+        }
+    }
+}
+
+impl From<CprError> for JsValue {
+    fn from(err: CprError) -> Self {
+        let error = js_sys::Error::new(err.message());
+        // 设置失败时仍返回携带 message 的 Error
+        let _ = Reflect::set(
+            &error,
+            &JsValue::from_str("code"),
+            &JsValue::from_str(err.code()),
+        );
+        error.into()
+    }
+}
+
+impl From<image::ImageError> for CprError {
+    fn from(err: image::ImageError) -> Self {
+        match err {
+            image::ImageError::Decoding(_) => CprError::DecodeFailed(err.to_string()),
+            image::ImageError::Limits(_) => CprError::InputTooLarge(err.to_string()),
+            _ => CprError::EncodeFailed(err.to_string()),
+        }
+    }
+}
+
+impl From<image::error::UnsupportedError> for CprError {
+    fn from(err: image::error::UnsupportedError) -> Self {
+        CprError::EncodeFailed(err.to_string())
+    }
+}
+
+impl From<jpeg_encoder::EncodingError> for CprError {
+    fn from(err: jpeg_encoder::EncodingError) -> Self {
+        CprError::EncodeFailed(err.to_string())
+    }
+}
+
+impl From<tiff::TiffError> for CprError {
+    fn from(err: tiff::TiffError) -> Self {
+        CprError::EncodeFailed(err.to_string())
+    }
+}
+
+impl From<std::io::Error> for CprError {
+    fn from(err: std::io::Error) -> Self {
+        CprError::EncodeFailed(err.to_string())
+    }
+}
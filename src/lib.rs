@@ -1,19 +1,170 @@
+use ab_glyph::{FontArc, PxScale};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use image::codecs::avif::AvifEncoder;
+use image::codecs::bmp::BmpEncoder;
+use image::codecs::gif::{GifDecoder, GifEncoder, Repeat};
+use image::codecs::ico::{IcoEncoder, IcoFrame};
 use image::codecs::jpeg::JpegEncoder;
-use image::codecs::png::{CompressionType, PngEncoder};
+use image::codecs::png::{CompressionType, FilterType as PngFilterType, PngEncoder};
+use image::codecs::qoi::QoiEncoder;
 use image::codecs::webp::WebPEncoder;
 use image::imageops::{resize, FilterType};
-use image::{DynamicImage, ImageFormat};
+use image::metadata::Orientation;
+use image::{
+    AnimationDecoder, Delay, DynamicImage, ExtendedColorType, Frame, GrayImage, ImageBuffer,
+    ImageDecoder, ImageEncoder, ImageFormat, Luma, Rgb, RgbImage, Rgba, RgbaImage,
+};
 use js_sys::{Reflect, Uint8Array};
-use std::io::Cursor;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::io::{Cursor, Write};
+use tiff::encoder::colortype;
+use tiff::encoder::compression::{Compression, Deflate, Lzw, Uncompressed};
 use wasm_bindgen::prelude::*;
 
+mod error;
+
+use error::CprError;
+
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(js_namespace = console)]
+    fn warn(message: &str);
+}
+
 struct ImageConfig {
-    format: String,                     // 输入图像格式
-    crop: Option<CropConfig>,           // 裁剪参数
-    size: Option<SizeConfig>,           // 缩放参数
-    watermark: Option<WatermarkConfig>, // 水印参数
-    output_format: Option<String>,      // 输出格式
-    quality: Option<u8>,                // 输出质量（例如 JPEG）
+    format: Option<String>,                // 输入图像格式，缺省时按文件头自动识别
+    max_pixels: u64,                       // 输入图像像素数上限，防止解压炸弹
+    max_input_bytes: Option<usize>,        // 输入数据字节数上限
+    preserve_bit_depth: bool,              // 是否保留 16 位等高位深，不经 8 位中转
+    deadline: Deadline,                    // 处理超时时间
+    frame_index: Option<u32>,              // 动图中参与处理的帧序号（从 0 开始）
+    ico_index: Option<u32>, // ICO/CUR 中参与处理的图像序号（从 0 开始），默认取最大尺寸
+    animated: bool,         // 是否逐帧处理 GIF 动图并输出动图
+    auto_orient: bool,      // 是否按 EXIF 方向信息自动旋转
+    trim: Option<TrimConfig>, // 去除纯色边框参数
+    denoise: Option<DenoiseConfig>, // 中值滤波降噪参数
+    chroma_key: Option<ChromaKeyConfig>, // 抠除纯色背景参数
+    crop: Option<CropConfig>, // 裁剪参数
+    crop_aspect: Option<CropAspectConfig>, // 按宽高比居中裁剪参数
+    entropy_crop: bool,     // 按宽高比裁剪时是否选取信息熵最高的区域
+    rotate: Option<u32>,    // 顺时针旋转角度 (90 / 180 / 270)
+    flip_horizontal: bool,  // 水平翻转
+    flip_vertical: bool,    // 垂直翻转
+    size: Option<SizeConfig>, // 缩放参数
+    linear_resize: bool,    // 是否在线性光空间缩放
+    premultiply_alpha: bool, // 缩放时是否预乘透明度，避免透明边缘出现暗边
+    thumbnail: Option<ThumbnailConfig>, // 缩略图参数
+    pad: Option<PadConfig>, // 等比缩放后居中填充到固定尺寸
+    grayscale: bool,        // 是否转为灰度图
+    auto_levels: Option<AutoLevelsConfig>, // 自动色阶参数
+    brightness: Option<i32>, // 亮度调整 (-255 - 255)
+    contrast: Option<f32>,  // 对比度调整
+    blur: Option<f32>,      // 高斯模糊 sigma
+    sharpen: Option<SharpenConfig>, // 锐化参数 (USM)
+    sepia: bool,            // 是否应用复古褐色滤镜
+    invert: bool,           // 是否反色
+    gamma: Option<f32>,     // 伽马校正值（例如 2.2）
+    saturation: Option<f32>, // 饱和度倍数 (1.0 为不变)
+    edges: bool,            // 是否输出 Sobel 边缘强度图
+    threshold: Option<Threshold>, // 二值化阈值
+    pixelate: Option<PixelateConfig>, // 马赛克参数
+    overlay: Option<OverlayConfig>, // 纯色或渐变叠加层参数
+    vignette: Option<VignetteConfig>, // 暗角参数
+    watermarks: Vec<WatermarkConfig>, // 水印参数，按顺序依次叠加
+    text: Option<TextConfig>, // 文字水印参数
+    rounded_corners: Option<RoundedCornersConfig>, // 圆角遮罩参数
+    circle_crop: bool,      // 是否居中裁剪为圆形
+    operation_order: Vec<Stage>, // 裁剪、缩放、水印三个阶段的执行顺序
+    output_format: Option<String>, // 输出格式
+    force_reencode: bool,   // 无任何变换时是否仍重新编码，而不是直接返回原始字节
+    encode: EncodeOptions,  // 编码参数
+}
+
+// 可通过 operation_order 调整先后顺序的阶段
+#[derive(Clone, Copy, PartialEq)]
+enum Stage {
+    Crop,      // crop / crop_aspect
+    Resize,    // size
+    Watermark, // watermarks / text
+}
+
+const DEFAULT_OPERATION_ORDER: [Stage; 3] = [Stage::Crop, Stage::Resize, Stage::Watermark];
+
+// 超时只能在流水线各阶段之间协作式检查，无法打断单次编码调用
+struct Deadline {
+    expires_at: Option<f64>, // 到期时刻（毫秒时间戳），None 表示不限时
+}
+
+impl Deadline {
+    fn new(timeout_ms: Option<f64>) -> Self {
+        Deadline {
+            expires_at: timeout_ms.map(|timeout_ms| js_sys::Date::now() + timeout_ms),
+        }
+    }
+
+    fn check(&self, stage: &str) -> Result<(), CprError> {
+        match self.expires_at {
+            Some(expires_at) if js_sys::Date::now() > expires_at => Err(CprError::Timeout(
+                format!("'timeout_ms' exceeded before {}", stage),
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct EncodeOptions {
+    quality: Option<u8>,                                    // 输出质量（例如 JPEG）
+    target_size_bytes: Option<usize>,                       // JPEG 输出的目标字节数上限
+    target_size_downscale: bool, // 质量不足以满足 target_size_bytes 时是否逐步缩小尺寸
+    progressive: bool,           // 是否输出渐进式 JPEG
+    jpeg_subsampling: Option<jpeg_encoder::SamplingFactor>, // JPEG 色度子采样，缺省时由编码器决定
+    flatten_background: Rgba<u8>, // 输出 JPEG 时透明区域合成的背景色
+    on_alpha_to_jpeg: AlphaToJpeg, // 透明图像输出 JPEG 时的处理方式
+    dpi: Option<u16>,            // 写入 JPEG / PNG 的分辨率 (DPI)
+    lossless: Option<bool>,      // WebP 是否无损编码，缺省时按 quality 判断
+    png_compression: CompressionType, // PNG 压缩级别
+    png_filter: PngFilterType,   // PNG 行滤波器
+    png_palette: bool,           // 是否量化为调色板（索引色）PNG
+    max_colors: usize,           // 调色板 PNG 的最大颜色数 (2 - 256)
+    dither: bool,                // 调色板 PNG 与 GIF 量化时是否使用 Floyd–Steinberg 抖动
+    speed: u8,                   // AVIF 编码速度 (0 - 10)，越大越快
+    tiff_compression: TiffCompression, // TIFF 压缩方式
+    ico_sizes: Option<Vec<u32>>, // ICO 内打包的各尺寸（例如 16 / 32 / 48）
+    ico_auto_resize: bool,       // ICO 超过 256px 时是否自动缩小
+    strip_metadata: bool,        // 是否去除元数据
+    icc_profile: Option<Vec<u8>>, // 需透传的输入 ICC 配置文件
+    grayscale: bool,             // 流水线执行过 grayscale，输出 JPEG 时检查像素是否仍为灰度
+}
+
+#[derive(Clone, PartialEq)]
+enum AlphaToJpeg {
+    Flatten, // 合成到 flatten_background 上
+    Error,   // 拒绝编码，由调用方改用 PNG / WebP
+}
+
+#[derive(Clone)]
+enum TiffCompression {
+    None,
+    Lzw,
+    Deflate,
+}
+
+struct TrimConfig {
+    tolerance: u8,           // 各通道与边框颜色的最大允许差值
+    color: Option<Rgba<u8>>, // 边框颜色，缺省时取左上角像素
+}
+
+struct DenoiseConfig {
+    radius: u32, // 中值滤波窗口半径 (1 - 3)，窗口边长为 2 * radius + 1
+}
+
+struct ChromaKeyConfig {
+    color: Rgba<u8>, // 需要抠除的背景色，忽略其 Alpha
+    tolerance: u8,   // RGB 各通道与背景色的最大差值，在此范围内的像素变为透明
+    feather: u8,     // 容差内侧的柔化带宽度，带内 Alpha 随差值线性过渡以柔化边缘
 }
 
 struct CropConfig {
@@ -21,279 +172,6143 @@ struct CropConfig {
     y: u32,
     width: u32,
     height: u32,
+    unit: CropUnit,                          // 坐标单位
+    space: CropSpace,                        // 坐标所在的坐标系
+    stored_orientation: Option<Orientation>, // space 为 stored 且开启 auto_orient 时，由输入的 EXIF 方向填入
+}
+
+#[derive(PartialEq)]
+enum CropSpace {
+    Display, // 按 EXIF 方向校正后显示的图像
+    Stored,  // 文件中存储的像素，校正方向前
+}
+
+#[derive(PartialEq)]
+enum CropUnit {
+    Px,      // 像素
+    Percent, // 原图尺寸的百分比 (0 - 100)
+}
+
+struct CropAspectConfig {
+    ratio_w: u32,
+    ratio_h: u32,
 }
 
+#[derive(Clone)]
 struct SizeConfig {
+    width: Option<u32>, // 仅指定一边时按原图宽高比计算另一边
+    height: Option<u32>,
+    scale: Option<f64>,     // 按原图尺寸的倍数缩放，指定 width / height 时忽略
+    filter: Option<String>, // 缩放滤波器: nearest / triangle / catmull_rom / gaussian / lanczos3
+    downscale_filter: Option<String>, // 缩小方向使用的滤波器，缺省时使用 filter
+    upscale_filter: Option<String>, // 放大方向使用的滤波器，缺省时使用 filter
+    fit: Option<String>,    // 适配模式: stretch / contain / cover
+    allow_upscale: bool,    // 是否允许放大，关闭时目标尺寸不超过原图
+}
+
+struct ThumbnailConfig {
+    max_edge: u32, // 最长边上限，原图更小时不放大
+}
+
+struct PadConfig {
     width: u32,
     height: u32,
+    color: Rgba<u8>,        // 背景色
+    blur_fill: Option<f32>, // 以模糊放大的原图铺满背景时的模糊 sigma，指定时忽略 color
+}
+
+struct AutoLevelsConfig {
+    clip_percent: f32, // 两端各忽略的像素百分比，避免个别噪点影响拉伸
+}
+
+struct SharpenConfig {
+    sigma: f32,
+    threshold: i32,
+}
+
+enum Threshold {
+    Value(u8), // 亮度高于该值的像素变为白色，其余为黑色
+    Auto,      // 按 Otsu 法自动选取阈值
+}
+
+struct PixelateConfig {
+    block_size: u32,          // 马赛克块边长（像素）
+    region: Option<[u32; 4]>, // [x, y, width, height]，缺省时处理整张图像
+}
+
+struct OverlayConfig {
+    fill: OverlayFill, // 叠加的颜色
+    opacity: f32,      // 叠加层透明度 (0.0 - 1.0)，与颜色自身的 Alpha 相乘
+}
+
+enum OverlayFill {
+    Solid(Rgba<u8>),
+    Gradient(Rgba<u8>, Rgba<u8>, GradientDirection), // 起点颜色、终点颜色、方向
+}
+
+struct VignetteConfig {
+    strength: f32, // 四角最大压暗比例 (0.0 - 1.0)，0 时不生效
+    radius: f32,   // 开始压暗的位置，按中心到角落距离归一化 (0.0 - 1.0)
+}
+
+enum GradientDirection {
+    Vertical,   // 从上到下
+    Horizontal, // 从左到右
 }
 
 struct WatermarkConfig {
-    content: Vec<u8>,          // 水印图像数据
-    position: [u32; 4],        // [x, y, width, height]
-    opacity: f64,              // 水印透明度 (1 - 100)
-    use_watermark_alpha: bool, // 是否使用水印自身的 Alpha 通道
+    content: Vec<u8>,           // 水印图像数据
+    position: [i64; 4],         // [x, y, width, height]，仅 clip 模式下 x / y 可为负
+    opacity: f64,               // 水印透明度 (1 - 100)
+    use_watermark_alpha: bool,  // 是否使用水印自身的 Alpha 通道
+    tile: bool,                 // 是否平铺水印
+    anchor: Option<String>,     // 锚点位置，指定时忽略 position 中的 x / y
+    margin: u32,                // 锚点边距
+    rotation: Option<f32>,      // 顺时针旋转角度
+    scale_percent: Option<f64>, // 水印宽度占主图宽度的百分比，指定时忽略 position 中的宽高
+    clip: bool,                 // 是否允许水印超出图像边缘，仅叠加可见部分
+    blend_mode: BlendMode,      // 与主图的混合模式
+    fade: Option<Fade>,         // 透明度渐变方向，与 opacity 相乘
+    filter: FilterType,         // 水印缩放滤波器
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Fade {
+    LeftToRight, // 左侧不透明，向右渐隐
+    RightToLeft, // 右侧不透明，向左渐隐
+    TopToBottom, // 顶部不透明，向下渐隐
+    BottomToTop, // 底部不透明，向上渐隐
+}
+
+impl Fade {
+    // 水印内 (wx, wy) 处的透明度系数：起始边为 1，对边为 0
+    fn factor(self, wx: u32, wy: u32, width: u32, height: u32) -> f32 {
+        let ramp = |offset: u32, length: u32| {
+            if length > 1 {
+                offset as f32 / (length - 1) as f32
+            } else {
+                0.0
+            }
+        };
+        match self {
+            Fade::LeftToRight => 1.0 - ramp(wx, width),
+            Fade::RightToLeft => ramp(wx, width),
+            Fade::TopToBottom => 1.0 - ramp(wy, height),
+            Fade::BottomToTop => ramp(wy, height),
+        }
+    }
 }
 
+#[derive(Clone, Copy, PartialEq)]
+enum BlendMode {
+    Normal,   // 直接覆盖
+    Multiply, // 正片叠底，白色不改变主图
+    Screen,   // 滤色，黑色不改变主图
+    Overlay,  // 叠加：主图暗部正片叠底、亮部滤色，增强对比
+    Darken,   // 变暗：逐通道取较小值
+    Lighten,  // 变亮：逐通道取较大值
+}
+
+impl BlendMode {
+    // 按通道计算混合结果，再由 Alpha 与主图插值
+    fn blend(self, base: u8, top: u8) -> f32 {
+        let (base, top) = (base as f32, top as f32);
+        match self {
+            BlendMode::Normal => top,
+            BlendMode::Multiply => base * top / 255.0,
+            BlendMode::Screen => 255.0 - (255.0 - base) * (255.0 - top) / 255.0,
+            BlendMode::Overlay if base < 128.0 => 2.0 * base * top / 255.0,
+            BlendMode::Overlay => 255.0 - 2.0 * (255.0 - base) * (255.0 - top) / 255.0,
+            BlendMode::Darken => base.min(top),
+            BlendMode::Lighten => base.max(top),
+        }
+    }
+}
+
+struct TextConfig {
+    string: String,         // 水印文字
+    font_size: f32,         // 字号（像素）
+    color: Rgba<u8>,        // 文字颜色，由十六进制字符串解析
+    position: [u32; 2],     // [x, y]
+    anchor: Option<String>, // 锚点位置，指定时忽略 position
+    margin: u32,            // 锚点边距
+    font: Option<Vec<u8>>,  // 自定义字体数据，缺省使用内置字体
+}
+
+struct RoundedCornersConfig {
+    radius: CornerRadius,
+}
+
+enum CornerRadius {
+    Uniform(u32),        // 四角相同的半径（像素），超过短边一半时按一半处理
+    PerCorner([u32; 4]), // [左上, 右上, 右下, 左下]，各自不得超过宽、高的一半
+}
+
+// 默认输入像素数上限（5000 万像素）
+const DEFAULT_MAX_PIXELS: u64 = 50_000_000;
+
+// 内置默认字体
+const DEFAULT_FONT: &[u8] = include_bytes!("../fonts/DejaVuSans.ttf");
+
 impl ImageConfig {
-    fn from_js_value(configs: &JsValue) -> Result<Self, JsError> {
+    fn from_js_value(configs: &JsValue) -> Result<Self, CprError> {
         // 从 configs 中获取配置
-        let format = Reflect::get(configs, &JsValue::from_str("format"))
-            .map_err(|_| JsError::new("Failed to get 'format' from configs"))?
-            .as_string()
-            .ok_or(JsError::new("Input format must be a string"))?;
+        let format_value = Reflect::get(configs, &JsValue::from_str("format"))
+            .map_err(|_| CprError::InvalidConfig("Failed to get 'format' from configs".into()))?;
+        let format = if format_value.is_undefined() || format_value.is_null() {
+            None
+        } else {
+            Some(format_value.as_string().ok_or(CprError::InvalidConfig(
+                "Input format must be a string".into(),
+            ))?)
+        };
+
+        let max_pixels = Reflect::get(configs, &JsValue::from_str("max_pixels"))
+            .map_err(|_| CprError::InvalidConfig("Failed to get 'max_pixels' from configs".into()))?
+            .as_f64();
+        let max_input_bytes = Reflect::get(configs, &JsValue::from_str("max_input_bytes"))
+            .map_err(|_| {
+                CprError::InvalidConfig("Failed to get 'max_input_bytes' from configs".into())
+            })?
+            .as_f64();
+        for (name, value) in [
+            ("max_pixels", max_pixels),
+            ("max_input_bytes", max_input_bytes),
+        ] {
+            if let Some(value) = value {
+                if !value.is_finite() || value < 1.0 {
+                    return Err(CprError::InvalidConfig(format!(
+                        "'{}' must be a positive integer",
+                        name
+                    )));
+                }
+            }
+        }
+
+        let preserve_bit_depth = Reflect::get(configs, &JsValue::from_str("preserve_bit_depth"))
+            .map_err(|_| {
+                CprError::InvalidConfig("Failed to get 'preserve_bit_depth' from configs".into())
+            })?
+            .as_bool()
+            .unwrap_or(false);
+
+        let timeout_ms = Reflect::get(configs, &JsValue::from_str("timeout_ms"))
+            .map_err(|_| CprError::InvalidConfig("Failed to get 'timeout_ms' from configs".into()))?
+            .as_f64();
+        if let Some(timeout_ms) = timeout_ms {
+            if !timeout_ms.is_finite() || timeout_ms < 0.0 {
+                return Err(CprError::InvalidConfig(
+                    "'timeout_ms' must be a non-negative number".into(),
+                ));
+            }
+        }
+
+        let frame_index = Reflect::get(configs, &JsValue::from_str("frame_index"))
+            .map_err(|_| {
+                CprError::InvalidConfig("Failed to get 'frame_index' from configs".into())
+            })?
+            .as_f64();
+        if let Some(index) = frame_index {
+            if !index.is_finite() || index < 0.0 {
+                return Err(CprError::InvalidConfig(
+                    "'frame_index' must be a non-negative integer".into(),
+                ));
+            }
+        }
+
+        let ico_index = Reflect::get(configs, &JsValue::from_str("ico_index"))
+            .map_err(|_| CprError::InvalidConfig("Failed to get 'ico_index' from configs".into()))?
+            .as_f64();
+        if let Some(index) = ico_index {
+            if !index.is_finite() || index < 0.0 {
+                return Err(CprError::InvalidConfig(
+                    "'ico_index' must be a non-negative integer".into(),
+                ));
+            }
+        }
+
+        let animated = Reflect::get(configs, &JsValue::from_str("animated"))
+            .map_err(|_| CprError::InvalidConfig("Failed to get 'animated' from configs".into()))?
+            .as_bool()
+            .unwrap_or(false);
+
+        let auto_orient = Reflect::get(configs, &JsValue::from_str("auto_orient"))
+            .map_err(|_| {
+                CprError::InvalidConfig("Failed to get 'auto_orient' from configs".into())
+            })?
+            .as_bool()
+            .unwrap_or(false);
+
+        // 支持 true 或 { tolerance, color } 两种写法
+        let trim_value = Reflect::get(configs, &JsValue::from_str("trim"))
+            .map_err(|_| CprError::InvalidConfig("Failed to get 'trim' from configs".into()))?;
+        let trim = if let Some(trim_obj) = trim_value.dyn_ref::<js_sys::Object>() {
+            let tolerance = Reflect::get(trim_obj, &JsValue::from_str("tolerance"))
+                .map_err(|_| {
+                    CprError::InvalidConfig("Failed to get 'tolerance' from configs.trim".into())
+                })?
+                .as_f64()
+                .unwrap_or(0.0);
+            if !(0.0..=255.0).contains(&tolerance) {
+                return Err(CprError::InvalidConfig(
+                    "'trim.tolerance' must be between 0 and 255".into(),
+                ));
+            }
+            let color = match Reflect::get(trim_obj, &JsValue::from_str("color"))
+                .map_err(|_| {
+                    CprError::InvalidConfig("Failed to get 'color' from configs.trim".into())
+                })?
+                .as_string()
+            {
+                Some(hex) => Some(parse_hex_color(&hex)?),
+                None => None,
+            };
+            Some(TrimConfig {
+                tolerance: tolerance as u8,
+                color,
+            })
+        } else if trim_value.as_bool() == Some(true) {
+            Some(TrimConfig {
+                tolerance: 0,
+                color: None,
+            })
+        } else {
+            None
+        };
+
+        let denoise = if let Some(denoise_obj) =
+            Reflect::get(configs, &JsValue::from_str("denoise"))
+                .map_err(|_| {
+                    CprError::InvalidConfig("Failed to get 'denoise' from configs".into())
+                })?
+                .dyn_ref::<js_sys::Object>()
+        {
+            let radius = Reflect::get(denoise_obj, &JsValue::from_str("radius"))
+                .map_err(|_| {
+                    CprError::InvalidConfig("Failed to get 'radius' from configs.denoise".into())
+                })?
+                .as_f64()
+                .unwrap_or(1.0);
+            // 中值滤波开销随窗口面积增长，限制为较小的半径
+            if !(1.0..=3.0).contains(&radius) {
+                return Err(CprError::InvalidConfig(
+                    "'denoise.radius' must be between 1 and 3".into(),
+                ));
+            }
+            Some(DenoiseConfig {
+                radius: radius as u32,
+            })
+        } else {
+            None
+        };
+
+        let chroma_key = if let Some(chroma_key_obj) =
+            Reflect::get(configs, &JsValue::from_str("chroma_key"))
+                .map_err(|_| {
+                    CprError::InvalidConfig("Failed to get 'chroma_key' from configs".into())
+                })?
+                .dyn_ref::<js_sys::Object>()
+        {
+            let color = Reflect::get(chroma_key_obj, &JsValue::from_str("color"))
+                .map_err(|_| {
+                    CprError::InvalidConfig("Failed to get 'color' from configs.chroma_key".into())
+                })?
+                .as_string()
+                .ok_or(CprError::InvalidConfig(
+                    "'chroma_key.color' must be a hex color string".into(),
+                ))?;
+            let color = parse_hex_color(&color)?;
+            let tolerance = Reflect::get(chroma_key_obj, &JsValue::from_str("tolerance"))
+                .map_err(|_| {
+                    CprError::InvalidConfig(
+                        "Failed to get 'tolerance' from configs.chroma_key".into(),
+                    )
+                })?
+                .as_f64()
+                .unwrap_or(0.0);
+            if !(0.0..=255.0).contains(&tolerance) {
+                return Err(CprError::InvalidConfig(
+                    "'chroma_key.tolerance' must be between 0 and 255".into(),
+                ));
+            }
+            let feather = Reflect::get(chroma_key_obj, &JsValue::from_str("feather"))
+                .map_err(|_| {
+                    CprError::InvalidConfig(
+                        "Failed to get 'feather' from configs.chroma_key".into(),
+                    )
+                })?
+                .as_f64()
+                .unwrap_or(0.0);
+            if !(0.0..=tolerance).contains(&feather) {
+                return Err(CprError::InvalidConfig(
+                    "'chroma_key.feather' must be between 0 and 'chroma_key.tolerance'".into(),
+                ));
+            }
+            Some(ChromaKeyConfig {
+                color,
+                tolerance: tolerance as u8,
+                feather: feather as u8,
+            })
+        } else {
+            None
+        };
 
         // 验证 crop 是否是一个对象
         let crop = if let Some(crop_obj) = Reflect::get(configs, &JsValue::from_str("crop"))
-            .map_err(|_| JsError::new("Failed to get 'crop' from configs"))?
+            .map_err(|_| CprError::InvalidConfig("Failed to get 'crop' from configs".into()))?
             .dyn_ref::<js_sys::Object>()
         {
             let x = Reflect::get(crop_obj, &JsValue::from_str("x"))
-                .map_err(|_| JsError::new("Failed to get 'x' from configs.crop"))?
+                .map_err(|_| CprError::InvalidConfig("Failed to get 'x' from configs.crop".into()))?
                 .as_f64()
                 .unwrap_or(0.0) as u32;
             let y = Reflect::get(crop_obj, &JsValue::from_str("y"))
-                .map_err(|_| JsError::new("Failed to get 'y' from configs.crop"))?
+                .map_err(|_| CprError::InvalidConfig("Failed to get 'y' from configs.crop".into()))?
                 .as_f64()
                 .unwrap_or(0.0) as u32;
             let width = Reflect::get(crop_obj, &JsValue::from_str("width"))
-                .map_err(|_| JsError::new("Failed to get 'width' from configs.crop"))?
+                .map_err(|_| {
+                    CprError::InvalidConfig("Failed to get 'width' from configs.crop".into())
+                })?
                 .as_f64()
                 .unwrap_or(0.0) as u32;
             let height = Reflect::get(crop_obj, &JsValue::from_str("height"))
-                .map_err(|_| JsError::new("Failed to get 'height' from configs.crop"))?
+                .map_err(|_| {
+                    CprError::InvalidConfig("Failed to get 'height' from configs.crop".into())
+                })?
                 .as_f64()
                 .unwrap_or(0.0) as u32;
+            let unit = match Reflect::get(crop_obj, &JsValue::from_str("unit"))
+                .map_err(|_| {
+                    CprError::InvalidConfig("Failed to get 'unit' from configs.crop".into())
+                })?
+                .as_string()
+                .as_deref()
+            {
+                None | Some("px") => CropUnit::Px,
+                Some("percent") => CropUnit::Percent,
+                Some(_) => {
+                    return Err(CprError::InvalidConfig(
+                        "'crop.unit' must be 'px' or 'percent'".into(),
+                    ))
+                }
+            };
+            let crop_space = Reflect::get(crop_obj, &JsValue::from_str("crop_space"))
+                .map_err(|_| {
+                    CprError::InvalidConfig("Failed to get 'crop_space' from configs.crop".into())
+                })?
+                .as_string();
+            let space = match crop_space.as_deref() {
+                None | Some("stored") => CropSpace::Stored,
+                Some("display") => CropSpace::Display,
+                Some(_) => {
+                    return Err(CprError::InvalidConfig(
+                        "'crop.crop_space' must be 'display' or 'stored'".into(),
+                    ))
+                }
+            };
             Some(CropConfig {
                 x,
                 y,
                 width,
                 height,
+                unit,
+                space,
+                stored_orientation: None,
             })
         } else {
             None
         };
 
+        let crop_aspect = if let Some(aspect_obj) =
+            Reflect::get(configs, &JsValue::from_str("crop_aspect"))
+                .map_err(|_| {
+                    CprError::InvalidConfig("Failed to get 'crop_aspect' from configs".into())
+                })?
+                .dyn_ref::<js_sys::Object>()
+        {
+            let ratio_w = Reflect::get(aspect_obj, &JsValue::from_str("ratio_w"))
+                .map_err(|_| {
+                    CprError::InvalidConfig(
+                        "Failed to get 'ratio_w' from configs.crop_aspect".into(),
+                    )
+                })?
+                .as_f64()
+                .unwrap_or(0.0) as u32;
+            let ratio_h = Reflect::get(aspect_obj, &JsValue::from_str("ratio_h"))
+                .map_err(|_| {
+                    CprError::InvalidConfig(
+                        "Failed to get 'ratio_h' from configs.crop_aspect".into(),
+                    )
+                })?
+                .as_f64()
+                .unwrap_or(0.0) as u32;
+            if ratio_w == 0 || ratio_h == 0 {
+                return Err(CprError::InvalidConfig(
+                    "'crop_aspect.ratio_w' and 'crop_aspect.ratio_h' must be positive integers"
+                        .into(),
+                ));
+            }
+            Some(CropAspectConfig { ratio_w, ratio_h })
+        } else {
+            None
+        };
+
+        let entropy_crop = Reflect::get(configs, &JsValue::from_str("entropy_crop"))
+            .map_err(|_| {
+                CprError::InvalidConfig("Failed to get 'entropy_crop' from configs".into())
+            })?
+            .as_bool()
+            .unwrap_or(false);
+
+        let linear_resize = Reflect::get(configs, &JsValue::from_str("linear_resize"))
+            .map_err(|_| {
+                CprError::InvalidConfig("Failed to get 'linear_resize' from configs".into())
+            })?
+            .as_bool()
+            .unwrap_or(false);
+
+        let premultiply_alpha = Reflect::get(configs, &JsValue::from_str("premultiply_alpha"))
+            .map_err(|_| {
+                CprError::InvalidConfig("Failed to get 'premultiply_alpha' from configs".into())
+            })?
+            .as_bool()
+            .unwrap_or(true);
+
+        let rotate = Reflect::get(configs, &JsValue::from_str("rotate"))
+            .map_err(|_| CprError::InvalidConfig("Failed to get 'rotate' from configs".into()))?
+            .as_f64();
+        let rotate = validate_rotate(rotate)?;
+
+        let flip_horizontal = Reflect::get(configs, &JsValue::from_str("flip_horizontal"))
+            .map_err(|_| {
+                CprError::InvalidConfig("Failed to get 'flip_horizontal' from configs".into())
+            })?
+            .as_bool()
+            .unwrap_or(false);
+
+        let flip_vertical = Reflect::get(configs, &JsValue::from_str("flip_vertical"))
+            .map_err(|_| {
+                CprError::InvalidConfig("Failed to get 'flip_vertical' from configs".into())
+            })?
+            .as_bool()
+            .unwrap_or(false);
+
         let size = if let Some(size_obj) = Reflect::get(configs, &JsValue::from_str("size"))
-            .map_err(|_| JsError::new("Failed to get 'size' from configs"))?
+            .map_err(|_| CprError::InvalidConfig("Failed to get 'size' from configs".into()))?
             .dyn_ref::<js_sys::Object>()
         {
             let width = Reflect::get(size_obj, &JsValue::from_str("width"))
-                .map_err(|_| JsError::new("Failed to get 'crop' from configs.size"))?
+                .map_err(|_| {
+                    CprError::InvalidConfig("Failed to get 'width' from configs.size".into())
+                })?
                 .as_f64()
-                .unwrap_or(0.0) as u32;
+                .map(|w| w as u32);
             let height = Reflect::get(size_obj, &JsValue::from_str("height"))
-                .map_err(|_| JsError::new("Failed to get 'crop' from configs.height"))?
+                .map_err(|_| {
+                    CprError::InvalidConfig("Failed to get 'height' from configs.size".into())
+                })?
                 .as_f64()
-                .unwrap_or(0.0) as u32;
-            Some(SizeConfig { width, height })
+                .map(|h| h as u32);
+            let scale = Reflect::get(size_obj, &JsValue::from_str("scale"))
+                .map_err(|_| {
+                    CprError::InvalidConfig("Failed to get 'scale' from configs.size".into())
+                })?
+                .as_f64();
+            if let Some(scale) = scale {
+                if !scale.is_finite() || scale <= 0.0 {
+                    return Err(CprError::InvalidConfig(
+                        "'size.scale' must be a positive finite number".into(),
+                    ));
+                }
+                if width.is_some() || height.is_some() {
+                    warn("'size.scale' is ignored because 'width' or 'height' is set");
+                }
+            }
+            let filter = Reflect::get(size_obj, &JsValue::from_str("filter"))
+                .map_err(|_| {
+                    CprError::InvalidConfig("Failed to get 'filter' from configs.size".into())
+                })?
+                .as_string();
+            let downscale_filter = Reflect::get(size_obj, &JsValue::from_str("downscale_filter"))
+                .map_err(|_| {
+                    CprError::InvalidConfig(
+                        "Failed to get 'downscale_filter' from configs.size".into(),
+                    )
+                })?
+                .as_string();
+            let upscale_filter = Reflect::get(size_obj, &JsValue::from_str("upscale_filter"))
+                .map_err(|_| {
+                    CprError::InvalidConfig(
+                        "Failed to get 'upscale_filter' from configs.size".into(),
+                    )
+                })?
+                .as_string();
+            let fit = Reflect::get(size_obj, &JsValue::from_str("fit"))
+                .map_err(|_| {
+                    CprError::InvalidConfig("Failed to get 'fit' from configs.size".into())
+                })?
+                .as_string();
+            let allow_upscale = Reflect::get(size_obj, &JsValue::from_str("allow_upscale"))
+                .map_err(|_| {
+                    CprError::InvalidConfig(
+                        "Failed to get 'allow_upscale' from configs.size".into(),
+                    )
+                })?
+                .as_bool()
+                .unwrap_or(true);
+            Some(SizeConfig {
+                width,
+                height,
+                scale,
+                filter,
+                downscale_filter,
+                upscale_filter,
+                fit,
+                allow_upscale,
+            })
+        } else {
+            None
+        };
+
+        let thumbnail = if let Some(thumbnail_obj) =
+            Reflect::get(configs, &JsValue::from_str("thumbnail"))
+                .map_err(|_| {
+                    CprError::InvalidConfig("Failed to get 'thumbnail' from configs".into())
+                })?
+                .dyn_ref::<js_sys::Object>()
+        {
+            let max_edge = Reflect::get(thumbnail_obj, &JsValue::from_str("max_edge"))
+                .map_err(|_| {
+                    CprError::InvalidConfig(
+                        "Failed to get 'max_edge' from configs.thumbnail".into(),
+                    )
+                })?
+                .as_f64()
+                .ok_or(CprError::InvalidConfig(
+                    "'thumbnail.max_edge' must be a number".into(),
+                ))?;
+            if !max_edge.is_finite() || max_edge < 1.0 {
+                return Err(CprError::InvalidConfig(
+                    "'thumbnail.max_edge' must be a positive integer".into(),
+                ));
+            }
+            Some(ThumbnailConfig {
+                max_edge: max_edge as u32,
+            })
         } else {
             None
         };
 
-        let watermark = if let Some(wm_obj) = Reflect::get(configs, &JsValue::from_str("watermark"))
-            .map_err(|_| JsError::new("Failed to get 'watermark' from configs"))?
+        let pad = if let Some(pad_obj) = Reflect::get(configs, &JsValue::from_str("pad"))
+            .map_err(|_| CprError::InvalidConfig("Failed to get 'pad' from configs".into()))?
             .dyn_ref::<js_sys::Object>()
         {
-            let content = Reflect::get(&wm_obj, &JsValue::from_str("content"))
-                .map_err(|_| JsError::new("Failed to get 'content' from watermark"))?;
-            let content_bytes = if let Some(content_array) = content.dyn_ref::<Uint8Array>() {
-                content_array.to_vec()
-            } else {
-                return Err(JsError::new("'content' must be a Uint8Array"));
+            let width = Reflect::get(pad_obj, &JsValue::from_str("width"))
+                .map_err(|_| {
+                    CprError::InvalidConfig("Failed to get 'width' from configs.pad".into())
+                })?
+                .as_f64()
+                .ok_or(CprError::InvalidConfig(
+                    "'pad.width' must be a number".into(),
+                ))?;
+            let height = Reflect::get(pad_obj, &JsValue::from_str("height"))
+                .map_err(|_| {
+                    CprError::InvalidConfig("Failed to get 'height' from configs.pad".into())
+                })?
+                .as_f64()
+                .ok_or(CprError::InvalidConfig(
+                    "'pad.height' must be a number".into(),
+                ))?;
+            if !width.is_finite() || !height.is_finite() || width < 1.0 || height < 1.0 {
+                return Err(CprError::InvalidConfig(
+                    "'pad.width' and 'pad.height' must be positive integers".into(),
+                ));
+            }
+            let color = match Reflect::get(pad_obj, &JsValue::from_str("color"))
+                .map_err(|_| {
+                    CprError::InvalidConfig("Failed to get 'color' from configs.pad".into())
+                })?
+                .as_string()
+            {
+                Some(hex) => parse_hex_color(&hex)?,
+                None => Rgba([255, 255, 255, 255]),
             };
+            // 支持 true（使用默认 sigma）或数值 sigma 两种写法
+            let blur_fill_value =
+                Reflect::get(pad_obj, &JsValue::from_str("blur_fill")).map_err(|_| {
+                    CprError::InvalidConfig("Failed to get 'blur_fill' from configs.pad".into())
+                })?;
+            let blur_fill = match (blur_fill_value.as_bool(), blur_fill_value.as_f64()) {
+                (Some(true), _) => Some(PAD_BLUR_FILL_SIGMA),
+                (_, Some(sigma)) if !sigma.is_finite() || sigma <= 0.0 => {
+                    return Err(CprError::InvalidConfig(
+                        "'pad.blur_fill' must be true or a positive number".into(),
+                    ))
+                }
+                (_, sigma) => sigma.map(|sigma| sigma as f32),
+            };
+            Some(PadConfig {
+                width: width as u32,
+                height: height as u32,
+                color,
+                blur_fill,
+            })
+        } else {
+            None
+        };
 
-            let position = Reflect::get(wm_obj, &JsValue::from_str("position"))
-                .map_err(|_| JsError::new("Failed to get 'position' from configs.watermark"))?
-                .dyn_into::<js_sys::Array>()
-                .map(|arr| {
-                    let x = arr.get(0).as_f64().unwrap_or(0.0) as u32;
-                    let y = arr.get(1).as_f64().unwrap_or(0.0) as u32;
-                    let width = arr.get(2).as_f64().unwrap_or(0.0) as u32;
-                    let height = arr.get(3).as_f64().unwrap_or(0.0) as u32;
-                    [x, y, width, height]
-                })
-                .unwrap_or([0, 0, 0, 0]);
+        let grayscale = Reflect::get(configs, &JsValue::from_str("grayscale"))
+            .map_err(|_| CprError::InvalidConfig("Failed to get 'grayscale' from configs".into()))?
+            .as_bool()
+            .unwrap_or(false);
 
-            let opacity = Reflect::get(wm_obj, &JsValue::from_str("opacity"))
-                .map_err(|_| JsError::new("Failed to get 'opacity' from configs.watermark"))?
+        // 支持 true 或 { clip_percent } 两种写法
+        let auto_levels_value =
+            Reflect::get(configs, &JsValue::from_str("auto_levels")).map_err(|_| {
+                CprError::InvalidConfig("Failed to get 'auto_levels' from configs".into())
+            })?;
+        let auto_levels = if let Some(auto_levels_obj) =
+            auto_levels_value.dyn_ref::<js_sys::Object>()
+        {
+            let clip_percent = Reflect::get(auto_levels_obj, &JsValue::from_str("clip_percent"))
+                .map_err(|_| {
+                    CprError::InvalidConfig(
+                        "Failed to get 'clip_percent' from configs.auto_levels".into(),
+                    )
+                })?
                 .as_f64()
-                .unwrap_or(100.0);
+                .unwrap_or(0.0);
+            if !(0.0..50.0).contains(&clip_percent) {
+                return Err(CprError::InvalidConfig(
+                    "'auto_levels.clip_percent' must be at least 0 and less than 50".into(),
+                ));
+            }
+            Some(AutoLevelsConfig {
+                clip_percent: clip_percent as f32,
+            })
+        } else if auto_levels_value.as_bool() == Some(true) {
+            Some(AutoLevelsConfig { clip_percent: 0.0 })
+        } else {
+            None
+        };
 
-            // 验证 opacity 是否在合理范围内
-            if opacity < 0.0 || opacity > 100.0 {
-                return Err(JsError::new("'opacity' must be between 0 and 100"));
+        let brightness = Reflect::get(configs, &JsValue::from_str("brightness"))
+            .map_err(|_| CprError::InvalidConfig("Failed to get 'brightness' from configs".into()))?
+            .as_f64();
+        if let Some(b) = brightness {
+            if !(-255.0..=255.0).contains(&b) {
+                return Err(CprError::InvalidConfig(
+                    "'brightness' must be between -255 and 255".into(),
+                ));
             }
+        }
+        let brightness = brightness.map(|b| b as i32);
 
-            // 归一化到 0.0-1.0
-            let opacity = opacity / 100.0;
+        let contrast = Reflect::get(configs, &JsValue::from_str("contrast"))
+            .map_err(|_| CprError::InvalidConfig("Failed to get 'contrast' from configs".into()))?
+            .as_f64();
+        if let Some(c) = contrast {
+            if !c.is_finite() {
+                return Err(CprError::InvalidConfig(
+                    "'contrast' must be a finite number".into(),
+                ));
+            }
+        }
+        let contrast = contrast.map(|c| c as f32);
 
-            let use_watermark_alpha =
-                Reflect::get(wm_obj, &JsValue::from_str("use_watermark_alpha"))
-                    .map_err(|_| {
-                        JsError::new("Failed to get 'use_watermark_alpha' from configs.watermark")
-                    })?
-                    .as_bool()
-                    .unwrap_or(false);
+        let blur = Reflect::get(configs, &JsValue::from_str("blur"))
+            .map_err(|_| CprError::InvalidConfig("Failed to get 'blur' from configs".into()))?
+            .as_f64();
+        if let Some(sigma) = blur {
+            if !sigma.is_finite() || sigma < 0.0 {
+                return Err(CprError::InvalidConfig(
+                    "'blur' must be a non-negative number".into(),
+                ));
+            }
+        }
+        let blur = blur.map(|sigma| sigma as f32);
 
-            Some(WatermarkConfig {
-                content: content_bytes,
-                position,
-                opacity,
-                use_watermark_alpha,
+        let sharpen = if let Some(sharpen_obj) =
+            Reflect::get(configs, &JsValue::from_str("sharpen"))
+                .map_err(|_| {
+                    CprError::InvalidConfig("Failed to get 'sharpen' from configs".into())
+                })?
+                .dyn_ref::<js_sys::Object>()
+        {
+            let sigma = Reflect::get(sharpen_obj, &JsValue::from_str("sigma"))
+                .map_err(|_| {
+                    CprError::InvalidConfig("Failed to get 'sigma' from configs.sharpen".into())
+                })?
+                .as_f64()
+                .unwrap_or(1.0);
+            if !sigma.is_finite() || sigma < 0.0 {
+                return Err(CprError::InvalidConfig(
+                    "'sharpen.sigma' must be a non-negative number".into(),
+                ));
+            }
+            let threshold = Reflect::get(sharpen_obj, &JsValue::from_str("threshold"))
+                .map_err(|_| {
+                    CprError::InvalidConfig("Failed to get 'threshold' from configs.sharpen".into())
+                })?
+                .as_f64()
+                .unwrap_or(0.0) as i32;
+            Some(SharpenConfig {
+                sigma: sigma as f32,
+                threshold,
             })
         } else {
             None
         };
 
-        let output_format = Reflect::get(configs, &JsValue::from_str("output_format"))
-            .map_err(|_| JsError::new("Failed to get 'opacity' from configs.watermark"))?
-            .as_string();
-
-        let quality = Reflect::get(configs, &JsValue::from_str("quality"))
-            .map_err(|_| JsError::new("Failed to get 'opacity' from configs.watermark"))?
-            .as_f64()
-            .map(|q| q as u8);
+        let sepia = Reflect::get(configs, &JsValue::from_str("sepia"))
+            .map_err(|_| CprError::InvalidConfig("Failed to get 'sepia' from configs".into()))?
+            .as_bool()
+            .unwrap_or(false);
 
-        Ok(ImageConfig {
-            format,
-            crop,
-            size,
-            watermark,
-            output_format,
-            quality,
-        })
-    }
-}
+        let invert = Reflect::get(configs, &JsValue::from_str("invert"))
+            .map_err(|_| CprError::InvalidConfig("Failed to get 'invert' from configs".into()))?
+            .as_bool()
+            .unwrap_or(false);
 
-fn apply_crop(mut img: DynamicImage, crop: &CropConfig) -> Result<DynamicImage, JsError> {
-    if crop.x + crop.width > img.width() || crop.y + crop.height > img.height() {
-        return Err(JsError::new("Crop dimensions exceed image bounds"));
-    }
-    Ok(img.crop(crop.x, crop.y, crop.width, crop.height))
-}
+        let gamma = Reflect::get(configs, &JsValue::from_str("gamma"))
+            .map_err(|_| CprError::InvalidConfig("Failed to get 'gamma' from configs".into()))?
+            .as_f64();
+        if let Some(g) = gamma {
+            if !g.is_finite() || g <= 0.0 {
+                return Err(CprError::InvalidConfig(
+                    "'gamma' must be a positive finite number".into(),
+                ));
+            }
+        }
+        let gamma = gamma.map(|g| g as f32);
 
-fn apply_resize(img: &DynamicImage, size: &SizeConfig) -> DynamicImage {
-    let resized = match img {
-        DynamicImage::ImageRgba8(rgba) => {
-            resize(rgba, size.width, size.height, FilterType::Lanczos3)
-        }
-        _ => resize(
-            &img.to_rgba8(),
-            size.width,
-            size.height,
-            FilterType::Lanczos3,
-        ),
-    };
-    DynamicImage::ImageRgba8(resized)
-}
+        let saturation = Reflect::get(configs, &JsValue::from_str("saturation"))
+            .map_err(|_| CprError::InvalidConfig("Failed to get 'saturation' from configs".into()))?
+            .as_f64();
+        if let Some(value) = saturation {
+            if !value.is_finite() || value < 0.0 {
+                return Err(CprError::InvalidConfig(
+                    "'saturation' must be a non-negative number".into(),
+                ));
+            }
+        }
+        let saturation = saturation.map(|value| value as f32);
 
-fn apply_watermark(
-    img: &DynamicImage,
-    watermark: &WatermarkConfig,
-) -> Result<DynamicImage, JsError> {
-    let watermark_img = image::load_from_memory(&watermark.content)
-        .map_err(|e| JsError::new(&format!("Failed to load watermark: {}", e)))?;
+        let edges = Reflect::get(configs, &JsValue::from_str("edges"))
+            .map_err(|_| CprError::InvalidConfig("Failed to get 'edges' from configs".into()))?
+            .as_bool()
+            .unwrap_or(false);
 
-    if watermark.position.len() != 4 {
-        return Err(JsError::new(
-            "Watermark position must be an array of 4 numbers",
-        ));
-    }
-    let [x, y, width, height] = watermark.position;
-    if x + width > img.width() || y + height > img.height() {
-        return Err(JsError::new("Watermark position exceeds image bounds"));
-    }
-    let resized_watermark = resize(&watermark_img, width, height, FilterType::Lanczos3);
-    let watermark_rgba = DynamicImage::ImageRgba8(resized_watermark).to_rgba8();
+        // 支持 0 - 255 的数值或字符串 "auto"
+        let threshold = Reflect::get(configs, &JsValue::from_str("threshold")).map_err(|_| {
+            CprError::InvalidConfig("Failed to get 'threshold' from configs".into())
+        })?;
+        let threshold = match (threshold.as_string(), threshold.as_f64()) {
+            (Some(value), _) if value == "auto" => Some(Threshold::Auto),
+            (Some(value), _) => {
+                return Err(CprError::InvalidConfig(format!(
+                    "Unsupported threshold: {}",
+                    value
+                )))
+            }
+            (None, Some(value)) if !(0.0..=255.0).contains(&value) => {
+                return Err(CprError::InvalidConfig(
+                    "'threshold' must be between 0 and 255 or \"auto\"".into(),
+                ))
+            }
+            (None, value) => value.map(|value| Threshold::Value(value as u8)),
+        };
 
-    let mut img_rgba = match img {
-        DynamicImage::ImageRgba8(rgba) => rgba.clone(),
-        _ => img.to_rgba8(),
-    };
+        let pixelate = if let Some(pixelate_obj) =
+            Reflect::get(configs, &JsValue::from_str("pixelate"))
+                .map_err(|_| {
+                    CprError::InvalidConfig("Failed to get 'pixelate' from configs".into())
+                })?
+                .dyn_ref::<js_sys::Object>()
+        {
+            let block_size = Reflect::get(pixelate_obj, &JsValue::from_str("block_size"))
+                .map_err(|_| {
+                    CprError::InvalidConfig(
+                        "Failed to get 'block_size' from configs.pixelate".into(),
+                    )
+                })?
+                .as_f64()
+                .ok_or(CprError::InvalidConfig(
+                    "'pixelate.block_size' must be a number".into(),
+                ))?;
+            if !(block_size >= 1.0 && block_size.is_finite()) {
+                return Err(CprError::InvalidConfig(
+                    "'pixelate.block_size' must be at least 1".into(),
+                ));
+            }
+            let region = Reflect::get(pixelate_obj, &JsValue::from_str("region"))
+                .map_err(|_| {
+                    CprError::InvalidConfig("Failed to get 'region' from configs.pixelate".into())
+                })?
+                .dyn_into::<js_sys::Array>()
+                .ok()
+                .map(|arr| [0, 1, 2, 3].map(|i| arr.get(i).as_f64().unwrap_or(0.0) as u32));
+            Some(PixelateConfig {
+                block_size: block_size as u32,
+                region,
+            })
+        } else {
+            None
+        };
 
-    for (wx, wy, watermark_pixel) in watermark_rgba.enumerate_pixels() {
-        let main_x = x + wx;
-        let main_y = y + wy;
-        if main_x < img_rgba.width() && main_y < img_rgba.height() {
-            let main_pixel = img_rgba.get_pixel_mut(main_x, main_y);
-            let alpha = if watermark.use_watermark_alpha {
-                watermark_pixel[3]
-            } else {
-                (watermark_pixel[3] as f32 * watermark.opacity as f32) as u8
+        let overlay = if let Some(overlay_obj) =
+            Reflect::get(configs, &JsValue::from_str("overlay"))
+                .map_err(|_| {
+                    CprError::InvalidConfig("Failed to get 'overlay' from configs".into())
+                })?
+                .dyn_ref::<js_sys::Object>()
+        {
+            let color = Reflect::get(overlay_obj, &JsValue::from_str("color"))
+                .map_err(|_| {
+                    CprError::InvalidConfig("Failed to get 'color' from configs.overlay".into())
+                })?
+                .as_string();
+            let gradient = Reflect::get(overlay_obj, &JsValue::from_str("gradient"))
+                .map_err(|_| {
+                    CprError::InvalidConfig("Failed to get 'gradient' from configs.overlay".into())
+                })?
+                .dyn_into::<js_sys::Array>()
+                .ok();
+            let fill = match (color, gradient) {
+                (Some(color), None) => OverlayFill::Solid(parse_hex_color(&color)?),
+                (None, Some(gradient)) => {
+                    // [起点颜色, 终点颜色, 方向]，方向缺省为 vertical
+                    let stop = |i: u32| {
+                        gradient
+                            .get(i)
+                            .as_string()
+                            .ok_or(CprError::InvalidConfig(
+                                "'overlay.gradient' must start with two hex colors".into(),
+                            ))
+                            .and_then(|hex| parse_hex_color(&hex))
+                    };
+                    let direction =
+                        match gradient.get(2).as_string().as_deref() {
+                            None | Some("vertical") => GradientDirection::Vertical,
+                            Some("horizontal") => GradientDirection::Horizontal,
+                            Some(_) => return Err(CprError::InvalidConfig(
+                                "'overlay.gradient' direction must be 'vertical' or 'horizontal'"
+                                    .into(),
+                            )),
+                        };
+                    OverlayFill::Gradient(stop(0)?, stop(1)?, direction)
+                }
+                _ => {
+                    return Err(CprError::InvalidConfig(
+                        "Exactly one of 'overlay.color' or 'overlay.gradient' is required".into(),
+                    ))
+                }
             };
-            let alpha_f = alpha as f32 / 255.0;
-            main_pixel[0] = (main_pixel[0] as f32 * (1.0 - alpha_f)
-                + watermark_pixel[0] as f32 * alpha_f) as u8;
-            main_pixel[1] = (main_pixel[1] as f32 * (1.0 - alpha_f)
-                + watermark_pixel[1] as f32 * alpha_f) as u8;
-            main_pixel[2] = (main_pixel[2] as f32 * (1.0 - alpha_f)
-                + watermark_pixel[2] as f32 * alpha_f) as u8;
-        }
-    }
-    Ok(DynamicImage::ImageRgba8(img_rgba))
-}
+            let opacity = Reflect::get(overlay_obj, &JsValue::from_str("opacity"))
+                .map_err(|_| {
+                    CprError::InvalidConfig("Failed to get 'opacity' from configs.overlay".into())
+                })?
+                .as_f64()
+                .unwrap_or(100.0);
+            if !(0.0..=100.0).contains(&opacity) {
+                return Err(CprError::InvalidOpacity(
+                    "'overlay.opacity' must be between 0 and 100".into(),
+                ));
+            }
+            Some(OverlayConfig {
+                fill,
+                opacity: (opacity / 100.0) as f32,
+            })
+        } else {
+            None
+        };
 
-fn encode_image(
-    img: DynamicImage,
-    format: ImageFormat,
-    quality: Option<u8>,
-) -> Result<Vec<u8>, JsError> {
-    let mut buf = Cursor::new(Vec::new());
-    match format {
-        ImageFormat::Jpeg => {
+        let vignette = if let Some(vignette_obj) =
+            Reflect::get(configs, &JsValue::from_str("vignette"))
+                .map_err(|_| {
+                    CprError::InvalidConfig("Failed to get 'vignette' from configs".into())
+                })?
+                .dyn_ref::<js_sys::Object>()
+        {
+            let strength = Reflect::get(vignette_obj, &JsValue::from_str("strength"))
+                .map_err(|_| {
+                    CprError::InvalidConfig("Failed to get 'strength' from configs.vignette".into())
+                })?
+                .as_f64()
+                .unwrap_or(0.5);
+            if !(0.0..=1.0).contains(&strength) {
+                return Err(CprError::InvalidConfig(
+                    "'vignette.strength' must be between 0 and 1".into(),
+                ));
+            }
+            let radius = Reflect::get(vignette_obj, &JsValue::from_str("radius"))
+                .map_err(|_| {
+                    CprError::InvalidConfig("Failed to get 'radius' from configs.vignette".into())
+                })?
+                .as_f64()
+                .unwrap_or(0.5);
+            if !(0.0..1.0).contains(&radius) {
+                return Err(CprError::InvalidConfig(
+                    "'vignette.radius' must be at least 0 and less than 1".into(),
+                ));
+            }
+            Some(VignetteConfig {
+                strength: strength as f32,
+                radius: radius as f32,
+            })
+        } else {
+            None
+        };
+
+        // 兼容单个 watermark，与 watermarks 数组合并后按顺序叠加
+        let mut watermarks = Vec::new();
+        if let Some(wm_obj) = Reflect::get(configs, &JsValue::from_str("watermark"))
+            .map_err(|_| CprError::InvalidConfig("Failed to get 'watermark' from configs".into()))?
+            .dyn_ref::<js_sys::Object>()
+        {
+            watermarks.push(WatermarkConfig::from_js_value(wm_obj)?);
+        }
+        if let Ok(wm_array) = Reflect::get(configs, &JsValue::from_str("watermarks"))
+            .map_err(|_| CprError::InvalidConfig("Failed to get 'watermarks' from configs".into()))?
+            .dyn_into::<js_sys::Array>()
+        {
+            for wm_value in wm_array.iter() {
+                let wm_obj =
+                    wm_value
+                        .dyn_ref::<js_sys::Object>()
+                        .ok_or(CprError::InvalidConfig(
+                            "Each entry in 'watermarks' must be an object".into(),
+                        ))?;
+                watermarks.push(WatermarkConfig::from_js_value(wm_obj)?);
+            }
+        }
+
+        let text = if let Some(text_obj) = Reflect::get(configs, &JsValue::from_str("text"))
+            .map_err(|_| CprError::InvalidConfig("Failed to get 'text' from configs".into()))?
+            .dyn_ref::<js_sys::Object>()
+        {
+            let string = Reflect::get(text_obj, &JsValue::from_str("string"))
+                .map_err(|_| {
+                    CprError::InvalidConfig("Failed to get 'string' from configs.text".into())
+                })?
+                .as_string()
+                .ok_or(CprError::InvalidConfig(
+                    "'text.string' must be a string".into(),
+                ))?;
+
+            let font_size = Reflect::get(text_obj, &JsValue::from_str("font_size"))
+                .map_err(|_| {
+                    CprError::InvalidConfig("Failed to get 'font_size' from configs.text".into())
+                })?
+                .as_f64()
+                .unwrap_or(24.0);
+            if !font_size.is_finite() || font_size <= 0.0 {
+                return Err(CprError::InvalidConfig(
+                    "'text.font_size' must be a positive number".into(),
+                ));
+            }
+
+            let color = match Reflect::get(text_obj, &JsValue::from_str("color"))
+                .map_err(|_| {
+                    CprError::InvalidConfig("Failed to get 'color' from configs.text".into())
+                })?
+                .as_string()
+            {
+                Some(hex) => parse_hex_color(&hex)?,
+                None => Rgba([255, 255, 255, 255]),
+            };
+
+            let position = Reflect::get(text_obj, &JsValue::from_str("position"))
+                .map_err(|_| {
+                    CprError::InvalidConfig("Failed to get 'position' from configs.text".into())
+                })?
+                .dyn_into::<js_sys::Array>()
+                .map(|arr| {
+                    let x = arr.get(0).as_f64().unwrap_or(0.0) as u32;
+                    let y = arr.get(1).as_f64().unwrap_or(0.0) as u32;
+                    [x, y]
+                })
+                .unwrap_or([0, 0]);
+
+            let anchor = Reflect::get(text_obj, &JsValue::from_str("anchor"))
+                .map_err(|_| {
+                    CprError::InvalidConfig("Failed to get 'anchor' from configs.text".into())
+                })?
+                .as_string();
+
+            let margin = Reflect::get(text_obj, &JsValue::from_str("margin"))
+                .map_err(|_| {
+                    CprError::InvalidConfig("Failed to get 'margin' from configs.text".into())
+                })?
+                .as_f64()
+                .unwrap_or(0.0) as u32;
+
+            let font = Reflect::get(text_obj, &JsValue::from_str("font")).map_err(|_| {
+                CprError::InvalidConfig("Failed to get 'font' from configs.text".into())
+            })?;
+            let font = if font.is_undefined() || font.is_null() {
+                None
+            } else if let Some(font_array) = font.dyn_ref::<Uint8Array>() {
+                Some(font_array.to_vec())
+            } else {
+                return Err(CprError::InvalidConfig(
+                    "'text.font' must be a Uint8Array".into(),
+                ));
+            };
+
+            Some(TextConfig {
+                string,
+                font_size: font_size as f32,
+                color,
+                position,
+                anchor,
+                margin,
+                font,
+            })
+        } else {
+            None
+        };
+
+        let rounded_corners = if let Some(rounded_obj) =
+            Reflect::get(configs, &JsValue::from_str("rounded_corners"))
+                .map_err(|_| {
+                    CprError::InvalidConfig("Failed to get 'rounded_corners' from configs".into())
+                })?
+                .dyn_ref::<js_sys::Object>()
+        {
+            // 支持单个数值或 [左上, 右上, 右下, 左下] 四个数值
+            let radius_value =
+                Reflect::get(rounded_obj, &JsValue::from_str("radius")).map_err(|_| {
+                    CprError::InvalidConfig(
+                        "Failed to get 'radius' from configs.rounded_corners".into(),
+                    )
+                })?;
+            let invalid = || {
+                CprError::InvalidConfig(
+                    "'rounded_corners.radius' must be a non-negative number or an array of 4"
+                        .into(),
+                )
+            };
+            let to_radius = |value: JsValue| match value.as_f64() {
+                Some(radius) if radius.is_finite() && radius >= 0.0 => Ok(radius as u32),
+                _ => Err(invalid()),
+            };
+            let radius = match radius_value.dyn_into::<js_sys::Array>() {
+                Ok(arr) if arr.length() == 4 => CornerRadius::PerCorner([
+                    to_radius(arr.get(0))?,
+                    to_radius(arr.get(1))?,
+                    to_radius(arr.get(2))?,
+                    to_radius(arr.get(3))?,
+                ]),
+                Ok(_) => return Err(invalid()),
+                Err(value) => CornerRadius::Uniform(to_radius(value)?),
+            };
+            Some(RoundedCornersConfig { radius })
+        } else {
+            None
+        };
+
+        let circle_crop = Reflect::get(configs, &JsValue::from_str("circle_crop"))
+            .map_err(|_| {
+                CprError::InvalidConfig("Failed to get 'circle_crop' from configs".into())
+            })?
+            .as_bool()
+            .unwrap_or(false);
+
+        // 未列出的阶段须未配置，排在已列出阶段之后
+        let mut operation_order = Vec::new();
+        if let Ok(order_array) = Reflect::get(configs, &JsValue::from_str("operation_order"))
+            .map_err(|_| {
+                CprError::InvalidConfig("Failed to get 'operation_order' from configs".into())
+            })?
+            .dyn_into::<js_sys::Array>()
+        {
+            for value in order_array.iter() {
+                let stage = match value.as_string().as_deref() {
+                    Some("crop") => Stage::Crop,
+                    Some("resize") => Stage::Resize,
+                    Some("watermark") => Stage::Watermark,
+                    _ => return Err(CprError::InvalidConfig(
+                        "'operation_order' entries must be one of 'crop', 'resize' or 'watermark'"
+                            .into(),
+                    )),
+                };
+                if operation_order.contains(&stage) {
+                    return Err(CprError::InvalidConfig(
+                        "'operation_order' must not list a stage twice".into(),
+                    ));
+                }
+                operation_order.push(stage);
+            }
+            for stage in DEFAULT_OPERATION_ORDER {
+                let (name, configured) = match stage {
+                    Stage::Crop => ("crop", crop.is_some() || crop_aspect.is_some()),
+                    Stage::Resize => ("resize", size.is_some()),
+                    Stage::Watermark => ("watermark", !watermarks.is_empty() || text.is_some()),
+                };
+                match (operation_order.contains(&stage), configured) {
+                    (true, false) => {
+                        return Err(CprError::InvalidConfig(format!(
+                            "'operation_order' lists '{}' but no {} is configured",
+                            name, name
+                        )))
+                    }
+                    (false, true) => {
+                        return Err(CprError::InvalidConfig(format!(
+                            "'{}' is configured but missing from 'operation_order'",
+                            name
+                        )))
+                    }
+                    (false, false) => operation_order.push(stage),
+                    (true, true) => {}
+                }
+            }
+        } else {
+            operation_order.extend(DEFAULT_OPERATION_ORDER);
+        }
+
+        // 存储坐标只能映射到刚校正方向、尚未改变尺寸的图像上；不论是否显式指定都不做回退
+        if auto_orient
+            && crop
+                .as_ref()
+                .is_some_and(|crop| crop.space == CropSpace::Stored)
+            && (trim.is_some() || operation_order[0] != Stage::Crop)
+        {
+            return Err(CprError::InvalidConfig(
+                "'crop.crop_space' of 'stored' (the default) requires no 'trim' and 'crop' first in 'operation_order' when 'auto_orient' is on; use 'display' instead".into(),
+            ));
+        }
+
+        let output_format = Reflect::get(configs, &JsValue::from_str("output_format"))
+            .map_err(|_| {
+                CprError::InvalidConfig("Failed to get 'output_format' from configs".into())
+            })?
+            .as_string();
+
+        let force_reencode = Reflect::get(configs, &JsValue::from_str("force_reencode"))
+            .map_err(|_| {
+                CprError::InvalidConfig("Failed to get 'force_reencode' from configs".into())
+            })?
+            .as_bool()
+            .unwrap_or(false);
+
+        let encode = EncodeOptions::from_js_value(configs)?;
+
+        Ok(ImageConfig {
+            format,
+            max_pixels: max_pixels.map_or(DEFAULT_MAX_PIXELS, |value| value as u64),
+            max_input_bytes: max_input_bytes.map(|value| value as usize),
+            preserve_bit_depth,
+            deadline: Deadline::new(timeout_ms),
+            frame_index: frame_index.map(|index| index as u32),
+            ico_index: ico_index.map(|index| index as u32),
+            animated,
+            auto_orient,
+            trim,
+            denoise,
+            chroma_key,
+            crop,
+            crop_aspect,
+            entropy_crop,
+            rotate,
+            flip_horizontal,
+            flip_vertical,
+            size,
+            linear_resize,
+            premultiply_alpha,
+            thumbnail,
+            pad,
+            grayscale,
+            auto_levels,
+            brightness,
+            contrast,
+            blur,
+            sharpen,
+            sepia,
+            invert,
+            gamma,
+            saturation,
+            edges,
+            threshold,
+            pixelate,
+            overlay,
+            vignette,
+            watermarks,
+            text,
+            rounded_corners,
+            circle_crop,
+            operation_order,
+            output_format,
+            force_reencode,
+            encode,
+        })
+    }
+}
+
+impl WatermarkConfig {
+    fn from_js_value(wm_obj: &js_sys::Object) -> Result<Self, CprError> {
+        let content = Reflect::get(wm_obj, &JsValue::from_str("content")).map_err(|_| {
+            CprError::InvalidConfig("Failed to get 'content' from watermark".into())
+        })?;
+        let content_bytes = if let Some(content_array) = content.dyn_ref::<Uint8Array>() {
+            content_array.to_vec()
+        } else {
+            return Err(CprError::InvalidConfig(
+                "'content' must be a Uint8Array".into(),
+            ));
+        };
+        Self::with_content(wm_obj, content_bytes)
+    }
+
+    // 读取除图像数据外的放置参数，composite 的顶层配置同样使用这些字段
+    fn with_content(wm_obj: &js_sys::Object, content_bytes: Vec<u8>) -> Result<Self, CprError> {
+        let clip = Reflect::get(wm_obj, &JsValue::from_str("clip"))
+            .map_err(|_| {
+                CprError::InvalidConfig("Failed to get 'clip' from configs.watermark".into())
+            })?
+            .as_bool()
+            .unwrap_or(false);
+
+        let position = Reflect::get(wm_obj, &JsValue::from_str("position"))
+            .map_err(|_| {
+                CprError::InvalidConfig("Failed to get 'position' from configs.watermark".into())
+            })?
+            .dyn_into::<js_sys::Array>()
+            .map(|arr| {
+                // 非 clip 模式下负坐标仍按 0 处理
+                let value = |i: u32, signed: bool| {
+                    let value = arr.get(i).as_f64().unwrap_or(0.0);
+                    if signed {
+                        value as i64
+                    } else {
+                        value as u32 as i64
+                    }
+                };
+                [
+                    value(0, clip),
+                    value(1, clip),
+                    value(2, false),
+                    value(3, false),
+                ]
+            })
+            .unwrap_or([0, 0, 0, 0]);
+
+        let opacity = Reflect::get(wm_obj, &JsValue::from_str("opacity"))
+            .map_err(|_| {
+                CprError::InvalidConfig("Failed to get 'opacity' from configs.watermark".into())
+            })?
+            .as_f64()
+            .unwrap_or(100.0);
+
+        // 验证 opacity 是否在合理范围内
+        if !(0.0..=100.0).contains(&opacity) {
+            return Err(CprError::InvalidOpacity(
+                "'opacity' must be between 0 and 100".into(),
+            ));
+        }
+
+        // 归一化到 0.0-1.0
+        let opacity = opacity / 100.0;
+
+        let use_watermark_alpha = Reflect::get(wm_obj, &JsValue::from_str("use_watermark_alpha"))
+            .map_err(|_| {
+                CprError::InvalidConfig(
+                    "Failed to get 'use_watermark_alpha' from configs.watermark".into(),
+                )
+            })?
+            .as_bool()
+            .unwrap_or(false);
+
+        let tile = Reflect::get(wm_obj, &JsValue::from_str("tile"))
+            .map_err(|_| {
+                CprError::InvalidConfig("Failed to get 'tile' from configs.watermark".into())
+            })?
+            .as_bool()
+            .unwrap_or(false);
+
+        let anchor = Reflect::get(wm_obj, &JsValue::from_str("anchor"))
+            .map_err(|_| {
+                CprError::InvalidConfig("Failed to get 'anchor' from configs.watermark".into())
+            })?
+            .as_string();
+
+        let margin = Reflect::get(wm_obj, &JsValue::from_str("margin"))
+            .map_err(|_| {
+                CprError::InvalidConfig("Failed to get 'margin' from configs.watermark".into())
+            })?
+            .as_f64()
+            .unwrap_or(0.0) as u32;
+
+        let rotation = Reflect::get(wm_obj, &JsValue::from_str("rotation"))
+            .map_err(|_| {
+                CprError::InvalidConfig("Failed to get 'rotation' from configs.watermark".into())
+            })?
+            .as_f64();
+        if let Some(r) = rotation {
+            if !r.is_finite() {
+                return Err(CprError::InvalidConfig(
+                    "'watermark.rotation' must be a finite number".into(),
+                ));
+            }
+        }
+        let rotation = rotation.map(|r| r as f32);
+
+        let scale_percent = Reflect::get(wm_obj, &JsValue::from_str("scale_percent"))
+            .map_err(|_| {
+                CprError::InvalidConfig(
+                    "Failed to get 'scale_percent' from configs.watermark".into(),
+                )
+            })?
+            .as_f64();
+        if let Some(percent) = scale_percent {
+            if !(percent > 0.0 && percent <= 100.0) {
+                return Err(CprError::InvalidConfig(
+                    "'watermark.scale_percent' must be greater than 0 and at most 100".into(),
+                ));
+            }
+        }
+
+        let blend_mode = match Reflect::get(wm_obj, &JsValue::from_str("blend_mode"))
+            .map_err(|_| {
+                CprError::InvalidConfig("Failed to get 'blend_mode' from configs.watermark".into())
+            })?
+            .as_string()
+            .as_deref()
+        {
+            None | Some("normal") => BlendMode::Normal,
+            Some("multiply") => BlendMode::Multiply,
+            Some("screen") => BlendMode::Screen,
+            Some("overlay") => BlendMode::Overlay,
+            Some("darken") => BlendMode::Darken,
+            Some("lighten") => BlendMode::Lighten,
+            Some(_) => return Err(CprError::InvalidConfig(
+                "'blend_mode' must be one of 'normal', 'multiply', 'screen', 'overlay', 'darken' or 'lighten'".into(),
+            )),
+        };
+
+        let fade = match Reflect::get(wm_obj, &JsValue::from_str("fade"))
+            .map_err(|_| {
+                CprError::InvalidConfig("Failed to get 'fade' from configs.watermark".into())
+            })?
+            .as_string()
+            .as_deref()
+        {
+            None => None,
+            Some("left-to-right") => Some(Fade::LeftToRight),
+            Some("right-to-left") => Some(Fade::RightToLeft),
+            Some("top-to-bottom") => Some(Fade::TopToBottom),
+            Some("bottom-to-top") => Some(Fade::BottomToTop),
+            Some(_) => return Err(CprError::InvalidConfig(
+                "'fade' must be one of 'left-to-right', 'right-to-left', 'top-to-bottom' or 'bottom-to-top'".into(),
+            )),
+        };
+
+        // 与 size.filter 不同，无法识别的滤波器名直接报错
+        let filter = match Reflect::get(wm_obj, &JsValue::from_str("filter"))
+            .map_err(|_| {
+                CprError::InvalidConfig("Failed to get 'filter' from configs.watermark".into())
+            })?
+            .as_string()
+        {
+            None => FilterType::Lanczos3,
+            Some(name) => parse_filter_type(&name).ok_or(CprError::InvalidConfig(
+                "Watermark 'filter' must be one of 'nearest', 'triangle', 'catmull_rom', 'gaussian' or 'lanczos3'".into(),
+            ))?,
+        };
+
+        Ok(WatermarkConfig {
+            content: content_bytes,
+            position,
+            opacity,
+            use_watermark_alpha,
+            tile,
+            anchor,
+            margin,
+            rotation,
+            scale_percent,
+            clip,
+            blend_mode,
+            fade,
+            filter,
+        })
+    }
+}
+
+// 0 或超过 100 的质量会让编码器输出异常结果，须在转换为 u8 之前校验
+fn validate_quality(quality: Option<f64>) -> Result<Option<u8>, CprError> {
+    match quality {
+        Some(q) if !(1.0..=100.0).contains(&q) => Err(CprError::InvalidConfig(
+            "'quality' must be between 1 and 100".into(),
+        )),
+        _ => Ok(quality.map(|q| q as u8)),
+    }
+}
+
+// 只接受整数角度，避免 90.5 之类的取值被截断为 90
+fn validate_rotate(rotate: Option<f64>) -> Result<Option<u32>, CprError> {
+    match rotate {
+        Some(r) if ![90.0, 180.0, 270.0].contains(&r) => Err(CprError::InvalidConfig(
+            "'rotate' must be one of 90, 180 or 270".into(),
+        )),
+        _ => Ok(rotate.map(|r| r as u32)),
+    }
+}
+
+impl EncodeOptions {
+    fn from_js_value(configs: &JsValue) -> Result<Self, CprError> {
+        let quality = Reflect::get(configs, &JsValue::from_str("quality"))
+            .map_err(|_| CprError::InvalidConfig("Failed to get 'quality' from configs".into()))?
+            .as_f64();
+        let quality = validate_quality(quality)?;
+
+        let target_size_bytes = Reflect::get(configs, &JsValue::from_str("target_size_bytes"))
+            .map_err(|_| {
+                CprError::InvalidConfig("Failed to get 'target_size_bytes' from configs".into())
+            })?
+            .as_f64();
+        if let Some(bytes) = target_size_bytes {
+            if !(bytes >= 1.0 && bytes.is_finite()) {
+                return Err(CprError::InvalidConfig(
+                    "'target_size_bytes' must be a positive number".into(),
+                ));
+            }
+        }
+        let target_size_bytes = target_size_bytes.map(|bytes| bytes as usize);
+
+        let target_size_downscale =
+            Reflect::get(configs, &JsValue::from_str("target_size_downscale"))
+                .map_err(|_| {
+                    CprError::InvalidConfig(
+                        "Failed to get 'target_size_downscale' from configs".into(),
+                    )
+                })?
+                .as_bool()
+                .unwrap_or(false);
+
+        let progressive = Reflect::get(configs, &JsValue::from_str("progressive"))
+            .map_err(|_| {
+                CprError::InvalidConfig("Failed to get 'progressive' from configs".into())
+            })?
+            .as_bool()
+            .unwrap_or(false);
+
+        let jpeg_subsampling = match Reflect::get(configs, &JsValue::from_str("jpeg_subsampling"))
+            .map_err(|_| {
+                CprError::InvalidConfig("Failed to get 'jpeg_subsampling' from configs".into())
+            })?
+            .as_string()
+            .as_deref()
+        {
+            None => None,
+            Some("444") => Some(jpeg_encoder::SamplingFactor::R_4_4_4),
+            Some("422") => Some(jpeg_encoder::SamplingFactor::R_4_2_2),
+            Some("420") => Some(jpeg_encoder::SamplingFactor::R_4_2_0),
+            Some(_) => {
+                return Err(CprError::InvalidConfig(
+                    "'jpeg_subsampling' must be one of '444', '422' or '420'".into(),
+                ))
+            }
+        };
+
+        let flatten_background =
+            match Reflect::get(configs, &JsValue::from_str("flatten_background"))
+                .map_err(|_| {
+                    CprError::InvalidConfig(
+                        "Failed to get 'flatten_background' from configs".into(),
+                    )
+                })?
+                .as_string()
+            {
+                Some(hex) => parse_hex_color(&hex)?,
+                None => Rgba([255, 255, 255, 255]),
+            };
+
+        let on_alpha_to_jpeg = match Reflect::get(configs, &JsValue::from_str("on_alpha_to_jpeg"))
+            .map_err(|_| {
+                CprError::InvalidConfig("Failed to get 'on_alpha_to_jpeg' from configs".into())
+            })?
+            .as_string()
+            .as_deref()
+        {
+            None | Some("flatten") => AlphaToJpeg::Flatten,
+            Some("error") => AlphaToJpeg::Error,
+            Some(_) => {
+                return Err(CprError::InvalidConfig(
+                    "'on_alpha_to_jpeg' must be one of 'flatten' or 'error'".into(),
+                ))
+            }
+        };
+
+        let dpi = Reflect::get(configs, &JsValue::from_str("dpi"))
+            .map_err(|_| CprError::InvalidConfig("Failed to get 'dpi' from configs".into()))?
+            .as_f64();
+        if let Some(dpi) = dpi {
+            if !(1.0..=65535.0).contains(&dpi) {
+                return Err(CprError::InvalidConfig(
+                    "'dpi' must be between 1 and 65535".into(),
+                ));
+            }
+        }
+        let dpi = dpi.map(|dpi| dpi as u16);
+
+        let lossless = Reflect::get(configs, &JsValue::from_str("lossless"))
+            .map_err(|_| CprError::InvalidConfig("Failed to get 'lossless' from configs".into()))?
+            .as_bool();
+
+        // 未指定时保持原有的 Best + Paeth
+        let png_compression = match Reflect::get(configs, &JsValue::from_str("png_compression"))
+            .map_err(|_| {
+                CprError::InvalidConfig("Failed to get 'png_compression' from configs".into())
+            })?
+            .as_string()
+            .as_deref()
+        {
+            None | Some("best") => CompressionType::Best,
+            Some("default") => CompressionType::Default,
+            Some("fast") => CompressionType::Fast,
+            Some(_) => {
+                return Err(CprError::InvalidConfig(
+                    "'png_compression' must be one of 'default', 'fast' or 'best'".into(),
+                ))
+            }
+        };
+
+        let png_filter = match Reflect::get(configs, &JsValue::from_str("png_filter"))
+            .map_err(|_| CprError::InvalidConfig("Failed to get 'png_filter' from configs".into()))?
+            .as_string()
+            .as_deref()
+        {
+            None | Some("paeth") => PngFilterType::Paeth,
+            Some("none") => PngFilterType::NoFilter,
+            Some("sub") => PngFilterType::Sub,
+            Some("up") => PngFilterType::Up,
+            Some("average") => PngFilterType::Avg,
+            Some("adaptive") => PngFilterType::Adaptive,
+            Some(_) => return Err(CprError::InvalidConfig(
+                "'png_filter' must be one of 'none', 'sub', 'up', 'average', 'paeth' or 'adaptive'"
+                    .into(),
+            )),
+        };
+
+        let png_palette = Reflect::get(configs, &JsValue::from_str("png_palette"))
+            .map_err(|_| {
+                CprError::InvalidConfig("Failed to get 'png_palette' from configs".into())
+            })?
+            .as_bool()
+            .unwrap_or(false);
+
+        let max_colors = Reflect::get(configs, &JsValue::from_str("max_colors"))
+            .map_err(|_| CprError::InvalidConfig("Failed to get 'max_colors' from configs".into()))?
+            .as_f64()
+            .unwrap_or(256.0);
+        if !(2.0..=256.0).contains(&max_colors) {
+            return Err(CprError::InvalidConfig(
+                "'max_colors' must be between 2 and 256".into(),
+            ));
+        }
+        let max_colors = max_colors as usize;
+
+        let dither = Reflect::get(configs, &JsValue::from_str("dither"))
+            .map_err(|_| CprError::InvalidConfig("Failed to get 'dither' from configs".into()))?
+            .as_bool()
+            .unwrap_or(false);
+
+        let speed = Reflect::get(configs, &JsValue::from_str("speed"))
+            .map_err(|_| CprError::InvalidConfig("Failed to get 'speed' from configs".into()))?
+            .as_f64()
+            .unwrap_or(4.0);
+        if !(0.0..=10.0).contains(&speed) {
+            return Err(CprError::InvalidConfig(
+                "'speed' must be between 0 and 10".into(),
+            ));
+        }
+        let speed = speed as u8;
+
+        let tiff_compression = match Reflect::get(configs, &JsValue::from_str("tiff_compression"))
+            .map_err(|_| {
+                CprError::InvalidConfig("Failed to get 'tiff_compression' from configs".into())
+            })?
+            .as_string()
+            .as_deref()
+        {
+            None | Some("none") => TiffCompression::None,
+            Some("lzw") => TiffCompression::Lzw,
+            Some("deflate") => TiffCompression::Deflate,
+            Some(_) => {
+                return Err(CprError::InvalidConfig(
+                    "'tiff_compression' must be one of 'none', 'lzw' or 'deflate'".into(),
+                ))
+            }
+        };
+
+        let ico_sizes = match Reflect::get(configs, &JsValue::from_str("ico_sizes"))
+            .map_err(|_| CprError::InvalidConfig("Failed to get 'ico_sizes' from configs".into()))?
+            .dyn_into::<js_sys::Array>()
+        {
+            Ok(arr) => {
+                let mut sizes = Vec::new();
+                for value in arr.iter() {
+                    match value.as_f64() {
+                        Some(size) if (1.0..=256.0).contains(&size) => sizes.push(size as u32),
+                        _ => {
+                            return Err(CprError::InvalidConfig(
+                                "'ico_sizes' entries must be between 1 and 256".into(),
+                            ))
+                        }
+                    }
+                }
+                if sizes.is_empty() {
+                    return Err(CprError::InvalidConfig(
+                        "'ico_sizes' must not be empty".into(),
+                    ));
+                }
+                Some(sizes)
+            }
+            Err(_) => None,
+        };
+
+        let ico_auto_resize = Reflect::get(configs, &JsValue::from_str("ico_auto_resize"))
+            .map_err(|_| {
+                CprError::InvalidConfig("Failed to get 'ico_auto_resize' from configs".into())
+            })?
+            .as_bool()
+            .unwrap_or(true);
+
+        let strip_metadata = Reflect::get(configs, &JsValue::from_str("strip_metadata"))
+            .map_err(|_| {
+                CprError::InvalidConfig("Failed to get 'strip_metadata' from configs".into())
+            })?
+            .as_bool()
+            .unwrap_or(true);
+
+        Ok(EncodeOptions {
+            quality,
+            target_size_bytes,
+            target_size_downscale,
+            progressive,
+            jpeg_subsampling,
+            flatten_background,
+            on_alpha_to_jpeg,
+            dpi,
+            lossless,
+            png_compression,
+            png_filter,
+            png_palette,
+            max_colors,
+            dither,
+            speed,
+            tiff_compression,
+            ico_sizes,
+            ico_auto_resize,
+            strip_metadata,
+            icc_profile: None,
+            grayscale: false,
+        })
+    }
+
+    // WebP 有损编码时返回所用质量，无损时返回 None
+    fn webp_lossy_quality(&self) -> Option<f32> {
+        match self.lossless {
+            Some(true) => None,
+            Some(false) => Some(self.quality.unwrap_or(80) as f32),
+            // 未显式指定时保持原有行为：质量低于 100 才走有损
+            None => self.quality.filter(|&quality| quality < 100).map(f32::from),
+        }
+    }
+}
+
+fn read_exif_orientation(input_data: &[u8]) -> Option<Orientation> {
+    let exif = exif::Reader::new()
+        .read_from_container(&mut Cursor::new(input_data))
+        .ok()?;
+    let orientation = exif
+        .get_field(exif::Tag::Orientation, exif::In::PRIMARY)?
+        .value
+        .get_uint(0)?;
+    Orientation::from_exif(u8::try_from(orientation).ok()?)
+}
+
+// 按存储坐标裁剪时记录输入的 EXIF 方向，供 crop_rect 换算坐标
+fn resolve_crop_orientation(config: &mut ImageConfig, input_data: &[u8]) {
+    if !config.auto_orient {
+        return;
+    }
+    if let Some(crop) = config
+        .crop
+        .as_mut()
+        .filter(|crop| crop.space == CropSpace::Stored)
+    {
+        crop.stored_orientation = read_exif_orientation(input_data);
+    }
+}
+
+fn apply_auto_orient(mut img: DynamicImage, input_data: &[u8]) -> DynamicImage {
+    // 没有或无法解析 EXIF 方向信息时保持原样
+    if let Some(orientation) = read_exif_orientation(input_data) {
+        img.apply_orientation(orientation);
+    }
+    img
+}
+
+impl CropConfig {
+    fn pixels(x: u32, y: u32, width: u32, height: u32) -> Self {
+        CropConfig {
+            x,
+            y,
+            width,
+            height,
+            unit: CropUnit::Px,
+            space: CropSpace::Display,
+            stored_orientation: None,
+        }
+    }
+
+    // 将裁剪区域换算为像素坐标 [x, y, width, height]
+    fn to_pixels(&self, img_width: u32, img_height: u32) -> Result<[u32; 4], CprError> {
+        if self.unit == CropUnit::Px {
+            return Ok([self.x, self.y, self.width, self.height]);
+        }
+        if self.x as u64 + self.width as u64 > 100 || self.y as u64 + self.height as u64 > 100 {
+            return Err(CprError::InvalidConfig("Crop percentages exceed 100: 'x' + 'width' and 'y' + 'height' must not be greater than 100".into()));
+        }
+        let to_px = |percent: u32, dim: u32| (percent as f64 * dim as f64 / 100.0).round() as u32;
+        Ok([
+            to_px(self.x, img_width),
+            to_px(self.y, img_height),
+            to_px(self.width, img_width),
+            to_px(self.height, img_height),
+        ])
+    }
+}
+
+fn apply_trim(img: DynamicImage, trim: &TrimConfig) -> Result<DynamicImage, CprError> {
+    let img_rgba = img.to_rgba8();
+    let (width, height) = img_rgba.dimensions();
+    if width == 0 || height == 0 {
+        return Ok(img);
+    }
+    let border = trim.color.unwrap_or(*img_rgba.get_pixel(0, 0));
+    let matches = |x: u32, y: u32| {
+        let pixel = img_rgba.get_pixel(x, y);
+        (0..4).all(|c| pixel[c].abs_diff(border[c]) <= trim.tolerance)
+    };
+    let row_matches = |y: u32| (0..width).all(|x| matches(x, y));
+    let column_matches = |x: u32, top: u32, bottom: u32| (top..bottom).all(|y| matches(x, y));
+
+    // 从四边向内扫描，整行或整列都与边框颜色一致时继续收缩
+    let Some(top) = (0..height).find(|&y| !row_matches(y)) else {
+        // 整张图都是边框颜色时保持原样
+        return Ok(img);
+    };
+    let bottom = (top..height)
+        .rev()
+        .find(|&y| !row_matches(y))
+        .unwrap_or(top)
+        + 1;
+    let left = (0..width)
+        .find(|&x| !column_matches(x, top, bottom))
+        .unwrap_or(0);
+    let right = (left..width)
+        .rev()
+        .find(|&x| !column_matches(x, top, bottom))
+        .unwrap_or(left)
+        + 1;
+    apply_crop(
+        img,
+        &CropConfig::pixels(left, top, right - left, bottom - top),
+    )
+}
+
+fn apply_denoise(img: DynamicImage, denoise: &DenoiseConfig) -> DynamicImage {
+    // median_filter 不接受空图像
+    if img.width() == 0 || img.height() == 0 {
+        return img;
+    }
+    DynamicImage::ImageRgba8(imageproc::filter::median_filter(
+        &img.into_rgba8(),
+        denoise.radius,
+        denoise.radius,
+    ))
+}
+
+fn apply_chroma_key(img: DynamicImage, chroma_key: &ChromaKeyConfig) -> DynamicImage {
+    let mut img_rgba = img.into_rgba8();
+    let tolerance = chroma_key.tolerance as f32;
+    let feather = chroma_key.feather as f32;
+    for pixel in img_rgba.pixels_mut() {
+        let distance = (0..3)
+            .map(|c| pixel[c].abs_diff(chroma_key.color[c]))
+            .max()
+            .unwrap_or(0) as f32;
+        if distance > tolerance {
+            continue;
+        }
+        // 柔化带内按到背景色的距离缩放 Alpha，带外（更接近背景色）完全透明
+        let keep = if feather > 0.0 {
+            ((distance - (tolerance - feather)) / feather).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        pixel[3] = (pixel[3] as f32 * keep).round() as u8;
+    }
+    DynamicImage::ImageRgba8(img_rgba)
+}
+
+fn apply_crop(mut img: DynamicImage, crop: &CropConfig) -> Result<DynamicImage, CprError> {
+    let [x, y, width, height] = crop_rect(crop, img.width(), img.height())?;
+    Ok(img.crop(x, y, width, height))
+}
+
+// 换算并校验裁剪区域，返回像素坐标 [x, y, width, height]
+fn crop_rect(crop: &CropConfig, img_width: u32, img_height: u32) -> Result<[u32; 4], CprError> {
+    // 存储坐标按校正前的尺寸换算与检查，再映射到校正后的图像上
+    let (stored_width, stored_height) = match crop.stored_orientation {
+        Some(orientation) if swaps_axes(orientation) => (img_height, img_width),
+        _ => (img_width, img_height),
+    };
+    let [x, y, width, height] = crop.to_pixels(stored_width, stored_height)?;
+    // 使用 checked_add 防止超大取值溢出后绕过边界检查
+    if !fits_within(x, width, stored_width) || !fits_within(y, height, stored_height) {
+        return Err(CprError::CropOutOfBounds(
+            "Crop dimensions exceed image bounds".into(),
+        ));
+    }
+    Ok(match crop.stored_orientation {
+        Some(orientation) => orient_rect(
+            [x, y, width, height],
+            stored_width,
+            stored_height,
+            orientation,
+        ),
+        None => [x, y, width, height],
+    })
+}
+
+fn swaps_axes(orientation: Orientation) -> bool {
+    matches!(
+        orientation,
+        Orientation::Rotate90
+            | Orientation::Rotate270
+            | Orientation::Rotate90FlipH
+            | Orientation::Rotate270FlipH
+    )
+}
+
+// 将存储图像 (width × height) 中的矩形映射到按 orientation 校正后的图像中
+fn orient_rect(
+    [x, y, rect_width, rect_height]: [u32; 4],
+    width: u32,
+    height: u32,
+    orientation: Orientation,
+) -> [u32; 4] {
+    let map = |px: u32, py: u32| match orientation {
+        Orientation::NoTransforms => (px, py),
+        Orientation::Rotate90 => (height - py, px),
+        Orientation::Rotate180 => (width - px, height - py),
+        Orientation::Rotate270 => (py, width - px),
+        Orientation::FlipHorizontal => (width - px, py),
+        Orientation::FlipVertical => (px, height - py),
+        Orientation::Rotate90FlipH => (py, px),
+        Orientation::Rotate270FlipH => (height - py, width - px),
+    };
+    // 以两个对角点确定映射后的矩形
+    let (x1, y1) = map(x, y);
+    let (x2, y2) = map(x + rect_width, y + rect_height);
+    [x1.min(x2), y1.min(y2), x1.abs_diff(x2), y1.abs_diff(y2)]
+}
+
+// 判断 [offset, offset + length) 是否落在 [0, bound) 内，溢出视为越界
+fn fits_within(offset: u32, length: u32, bound: u32) -> bool {
+    offset.checked_add(length).is_some_and(|end| end <= bound)
+}
+
+fn apply_rotate(img: DynamicImage, degrees: u32) -> Result<DynamicImage, CprError> {
+    match degrees {
+        90 => Ok(img.rotate90()),
+        180 => Ok(img.rotate180()),
+        270 => Ok(img.rotate270()),
+        _ => Err(CprError::InvalidConfig(
+            "'rotate' must be one of 90, 180 or 270".into(),
+        )),
+    }
+}
+
+fn apply_flip(img: DynamicImage, horizontal: bool, vertical: bool) -> DynamicImage {
+    let img = if horizontal { img.fliph() } else { img };
+    if vertical {
+        img.flipv()
+    } else {
+        img
+    }
+}
+
+fn apply_crop_aspect(
+    img: DynamicImage,
+    aspect: &CropAspectConfig,
+    entropy_crop: bool,
+) -> Result<DynamicImage, CprError> {
+    // 取给定宽高比下最大的裁剪区域，默认居中
+    let (width, height) = (img.width(), img.height());
+    let (crop_width, crop_height) = aspect_crop_size(aspect, width, height);
+    let (x, y) = if entropy_crop {
+        entropy_crop_origin(&img, crop_width, crop_height)
+    } else {
+        ((width - crop_width) / 2, (height - crop_height) / 2)
+    };
+    apply_crop(img, &CropConfig::pixels(x, y, crop_width, crop_height))
+}
+
+fn aspect_crop_size(aspect: &CropAspectConfig, width: u32, height: u32) -> (u32, u32) {
+    let (width, height) = (width as u64, height as u64);
+    let (ratio_w, ratio_h) = (aspect.ratio_w as u64, aspect.ratio_h as u64);
+    let (crop_width, crop_height) = if width * ratio_h > height * ratio_w {
+        ((height * ratio_w / ratio_h).max(1), height)
+    } else {
+        (width, (width * ratio_h / ratio_w).max(1))
+    };
+    (crop_width as u32, crop_height as u32)
+}
+
+// 信息熵扫描使用的缩略图最长边
+const ENTROPY_SCAN_SIZE: u32 = 128;
+
+fn entropy_crop_origin(img: &DynamicImage, crop_width: u32, crop_height: u32) -> (u32, u32) {
+    // 在缩小的灰度图上滑动裁剪窗口，选择灰度信息熵最高（细节最丰富）的位置
+    let gray = img
+        .thumbnail(ENTROPY_SCAN_SIZE, ENTROPY_SCAN_SIZE)
+        .into_luma8();
+    let scale_x = gray.width() as f64 / img.width() as f64;
+    let scale_y = gray.height() as f64 / img.height() as f64;
+    let window_width = ((crop_width as f64 * scale_x).round() as u32).clamp(1, gray.width());
+    let window_height = ((crop_height as f64 * scale_y).round() as u32).clamp(1, gray.height());
+
+    let mut best = (0, 0);
+    let mut best_entropy = f64::MIN;
+    for wy in 0..=gray.height() - window_height {
+        for wx in 0..=gray.width() - window_width {
+            let mut histogram = [0u32; 256];
+            for y in wy..wy + window_height {
+                for x in wx..wx + window_width {
+                    histogram[gray.get_pixel(x, y)[0] as usize] += 1;
+                }
+            }
+            let total = (window_width * window_height) as f64;
+            let entropy = histogram
+                .iter()
+                .filter(|&&count| count > 0)
+                .map(|&count| {
+                    let p = count as f64 / total;
+                    -p * p.log2()
+                })
+                .sum::<f64>();
+            if entropy > best_entropy {
+                best_entropy = entropy;
+                best = (wx, wy);
+            }
+        }
+    }
+
+    // 映射回原图坐标
+    let x = ((best.0 as f64 / scale_x).round() as u32).min(img.width() - crop_width);
+    let y = ((best.1 as f64 / scale_y).round() as u32).min(img.height() - crop_height);
+    (x, y)
+}
+
+fn parse_filter_type(name: &str) -> Option<FilterType> {
+    match name {
+        "nearest" => Some(FilterType::Nearest),
+        "triangle" => Some(FilterType::Triangle),
+        "catmull_rom" => Some(FilterType::CatmullRom),
+        "gaussian" => Some(FilterType::Gaussian),
+        "lanczos3" => Some(FilterType::Lanczos3),
+        _ => None,
+    }
+}
+
+fn resolve_size(
+    img_width: u32,
+    img_height: u32,
+    size: &SizeConfig,
+) -> Result<(u32, u32), CprError> {
+    let (orig_width, orig_height) = (img_width as f64, img_height as f64);
+    let (width, height) = match (size.width, size.height) {
+        (Some(width), Some(height)) => (width, height),
+        (Some(width), None) => {
+            let scale = width as f64 / orig_width;
+            (width, ((orig_height * scale).round() as u32).max(1))
+        }
+        (None, Some(height)) => {
+            let scale = height as f64 / orig_height;
+            (((orig_width * scale).round() as u32).max(1), height)
+        }
+        (None, None) => match size.scale {
+            Some(scale) => (
+                ((orig_width * scale).round() as u32).max(1),
+                ((orig_height * scale).round() as u32).max(1),
+            ),
+            None => {
+                return Err(CprError::InvalidConfig(
+                    "One of 'width', 'height' or 'scale' is required in configs.size".into(),
+                ))
+            }
+        },
+    };
+
+    // 禁止放大时按目标框宽高比整体缩小到不超过原图
+    if !size.allow_upscale && (width as f64 > orig_width || height as f64 > orig_height) {
+        let scale = f64::min(orig_width / width as f64, orig_height / height as f64);
+        return Ok((
+            ((width as f64 * scale).round() as u32).clamp(1, img_width),
+            ((height as f64 * scale).round() as u32).clamp(1, img_height),
+        ));
+    }
+    Ok((width, height))
+}
+
+fn apply_resize(
+    img: &DynamicImage,
+    size: &SizeConfig,
+    preserve_bit_depth: bool,
+    entropy_crop: bool,
+) -> Result<DynamicImage, CprError> {
+    let (width, height) = resolve_size(img.width(), img.height(), size)?;
+
+    // 目标尺寸与原图相同时各适配模式都不改变尺寸，跳过重采样，只转换为缩放结果的像素格式
+    if (width, height) == (img.width(), img.height()) {
+        return Ok(if preserve_bit_depth && is_high_bit_depth(img) {
+            img.clone()
+        } else {
+            DynamicImage::ImageRgba8(img.to_rgba8())
+        });
+    }
+
+    // 保留位深时 16 位输入按 16 位缩放，否则统一转为 8 位 RGBA
+    let resize_with = |img: &DynamicImage, width, height, filter| {
+        if preserve_bit_depth && is_high_bit_depth(img) {
+            resize_high_bit_depth(img, width, height, filter)
+        } else {
+            resize_rgba(img, width, height, filter)
+        }
+    };
+    // 宽高分别按缩小或放大选择滤波器，两者不同时先缩放宽度再缩放高度
+    let resize_to = |width, height| {
+        let filter_x = resize_filter(size, img.width(), width);
+        let filter_y = resize_filter(size, img.height(), height);
+        match (filter_x, filter_y) {
+            (Some(filter_x), Some(filter_y)) if filter_x != filter_y => {
+                let stretched = resize_with(img, width, img.height(), filter_x);
+                resize_with(&stretched, width, height, filter_y)
+            }
+            (Some(filter), _) | (None, Some(filter)) => resize_with(img, width, height, filter),
+            (None, None) => resize_with(img, width, height, default_filter(size)),
+        }
+    };
+
+    let (orig_width, orig_height) = (img.width() as f64, img.height() as f64);
+    match size.fit.as_deref().unwrap_or("stretch") {
+        "stretch" => Ok(resize_to(width, height)),
+        "contain" => {
+            let (fit_width, fit_height) = contain_size(img.width(), img.height(), width, height);
+            Ok(resize_to(fit_width, fit_height))
+        }
+        "cover" => {
+            // 等比缩放到铺满目标框，再裁掉溢出部分（默认居中）
+            let scale = f64::max(width as f64 / orig_width, height as f64 / orig_height);
+            let fill_width = ((orig_width * scale).round() as u32).max(width);
+            let fill_height = ((orig_height * scale).round() as u32).max(height);
+            let filled = resize_to(fill_width, fill_height);
+            let (x, y) = if entropy_crop {
+                entropy_crop_origin(&filled, width, height)
+            } else {
+                ((fill_width - width) / 2, (fill_height - height) / 2)
+            };
+            apply_crop(filled, &CropConfig::pixels(x, y, width, height))
+        }
+        _ => Err(CprError::InvalidConfig(
+            "'fit' must be one of 'stretch', 'contain' or 'cover'".into(),
+        )),
+    }
+}
+
+// sRGB 与线性光之间的转换表，以 16 位值为下标，8 位输入先展开为 16 位
+fn srgb_lut(to_linear: bool) -> Vec<u16> {
+    (0..=u16::MAX)
+        .map(|value| {
+            let v = value as f32 / 65535.0;
+            let converted = match to_linear {
+                true if v <= 0.04045 => v / 12.92,
+                true => ((v + 0.055) / 1.055).powf(2.4),
+                false if v <= 0.003_130_8 => v * 12.92,
+                false => 1.055 * v.powf(1.0 / 2.4) - 0.055,
+            };
+            (converted * 65535.0).round() as u16
+        })
+        .collect()
+}
+
+fn apply_srgb_lut(img: &mut ImageBuffer<Rgba<u16>, Vec<u16>>, lut: &[u16]) {
+    // 透明度是线性量，只转换 RGB 通道
+    for pixel in img.pixels_mut() {
+        for channel in &mut pixel.0[..3] {
+            *channel = lut[*channel as usize];
+        }
+    }
+}
+
+fn premultiply_rgba16(img: &mut ImageBuffer<Rgba<u16>, Vec<u16>>) {
+    for pixel in img.pixels_mut() {
+        let alpha = pixel[3] as u32;
+        for channel in &mut pixel.0[..3] {
+            *channel = ((*channel as u32 * alpha + 32767) / 65535) as u16;
+        }
+    }
+}
+
+fn unpremultiply_rgba16(img: &mut ImageBuffer<Rgba<u16>, Vec<u16>>) {
+    // 滤波器的过冲可能使颜色超过透明度，还原时截断到上限
+    for pixel in img.pixels_mut() {
+        let alpha = pixel[3] as u32;
+        for channel in &mut pixel.0[..3] {
+            *channel = match alpha {
+                0 => 0,
+                _ => ((*channel as u32 * 65535 + alpha / 2) / alpha).min(65535) as u16,
+            };
+        }
+    }
+}
+
+// 在线性光空间或按预乘透明度缩放，两者都以 16 位 RGBA 进行以免暗部与半透明边缘出现色带
+// 线性光：gamma 编码的 sRGB 值直接平均会使亮部细节变暗
+// 预乘：透明像素的颜色（通常为黑色）不再参与混合，避免抗锯齿边缘出现暗边
+fn apply_resize_rgba16(
+    img: &DynamicImage,
+    size: &SizeConfig,
+    preserve_bit_depth: bool,
+    entropy_crop: bool,
+    linear: bool,
+    premultiply: bool,
+) -> Result<DynamicImage, CprError> {
+    // 尺寸不变时交给 apply_resize 直接返回，避免 16 位往返转换改变像素
+    if resolve_size(img.width(), img.height(), size)? == (img.width(), img.height()) {
+        return apply_resize(img, size, preserve_bit_depth, entropy_crop);
+    }
+    let high_bit_depth = is_high_bit_depth(img);
+    let mut rgba = img.to_rgba16();
+    if linear {
+        apply_srgb_lut(&mut rgba, &srgb_lut(true));
+    }
+    if premultiply {
+        premultiply_rgba16(&mut rgba);
+    }
+    let resized = apply_resize(&DynamicImage::ImageRgba16(rgba), size, true, entropy_crop)?;
+    let mut rgba = resized.into_rgba16();
+    if premultiply {
+        unpremultiply_rgba16(&mut rgba);
+    }
+    if linear {
+        apply_srgb_lut(&mut rgba, &srgb_lut(false));
+    }
+    Ok(if preserve_bit_depth && high_bit_depth {
+        DynamicImage::ImageRgba16(rgba)
+    } else {
+        DynamicImage::ImageRgba8(DynamicImage::ImageRgba16(rgba).into_rgba8())
+    })
+}
+
+// 尺寸不变的方向返回 None，不参与滤波器选择
+fn resize_filter(size: &SizeConfig, from: u32, to: u32) -> Option<FilterType> {
+    let directional = match to.cmp(&from) {
+        std::cmp::Ordering::Less => size.downscale_filter.as_deref(),
+        std::cmp::Ordering::Greater => size.upscale_filter.as_deref(),
+        std::cmp::Ordering::Equal => return None,
+    };
+    Some(
+        directional
+            .and_then(parse_filter_type)
+            .unwrap_or_else(|| default_filter(size)),
+    )
+}
+
+fn default_filter(size: &SizeConfig) -> FilterType {
+    // 未指定或无法识别的滤波器默认使用 Lanczos3
+    size.filter
+        .as_deref()
+        .and_then(parse_filter_type)
+        .unwrap_or(FilterType::Lanczos3)
+}
+
+// 缩放后的输出尺寸，供不解码的校验使用
+fn resized_dimensions(
+    img_width: u32,
+    img_height: u32,
+    size: &SizeConfig,
+) -> Result<(u32, u32), CprError> {
+    let (width, height) = resolve_size(img_width, img_height, size)?;
+    match size.fit.as_deref().unwrap_or("stretch") {
+        "stretch" | "cover" => Ok((width, height)),
+        "contain" => Ok(contain_size(img_width, img_height, width, height)),
+        _ => Err(CprError::InvalidConfig(
+            "'fit' must be one of 'stretch', 'contain' or 'cover'".into(),
+        )),
+    }
+}
+
+fn contain_size(img_width: u32, img_height: u32, width: u32, height: u32) -> (u32, u32) {
+    // 等比缩放到完全落入目标框内
+    let (orig_width, orig_height) = (img_width as f64, img_height as f64);
+    let scale = f64::min(width as f64 / orig_width, height as f64 / orig_height);
+    (
+        ((orig_width * scale).round() as u32).clamp(1, width.max(1)),
+        ((orig_height * scale).round() as u32).clamp(1, height.max(1)),
+    )
+}
+
+fn apply_thumbnail(img: DynamicImage, thumbnail: &ThumbnailConfig) -> DynamicImage {
+    // 已小于上限时原样返回，避免放大
+    if img.width().max(img.height()) <= thumbnail.max_edge {
+        return img;
+    }
+    img.thumbnail(thumbnail.max_edge, thumbnail.max_edge)
+}
+
+fn thumbnail_size(img_width: u32, img_height: u32, thumbnail: &ThumbnailConfig) -> (u32, u32) {
+    // 与 DynamicImage::thumbnail 的等比缩放取整方式一致
+    if img_width.max(img_height) <= thumbnail.max_edge {
+        return (img_width, img_height);
+    }
+    let max_edge = thumbnail.max_edge as f64;
+    let scale = f64::min(max_edge / img_width as f64, max_edge / img_height as f64);
+    (
+        ((img_width as f64 * scale).round() as u32).max(1),
+        ((img_height as f64 * scale).round() as u32).max(1),
+    )
+}
+
+// blur_fill 为 true 时背景使用的模糊 sigma
+const PAD_BLUR_FILL_SIGMA: f32 = 20.0;
+
+fn apply_pad(img: DynamicImage, pad: &PadConfig) -> Result<DynamicImage, CprError> {
+    // 背景画布：纯色，或按 cover 铺满目标框并模糊的原图
+    let mut canvas = match pad.blur_fill {
+        Some(sigma) => {
+            let cover = SizeConfig {
+                width: Some(pad.width),
+                height: Some(pad.height),
+                scale: None,
+                filter: Some("triangle".into()), // 背景随后会被模糊，无需高质量滤波器
+                downscale_filter: None,
+                upscale_filter: None,
+                fit: Some("cover".into()),
+                allow_upscale: true,
+            };
+            apply_resize(&img, &cover, false, false)?
+                .blur(sigma)
+                .into_rgba8()
+        }
+        None => RgbaImage::from_pixel(pad.width, pad.height, pad.color),
+    };
+
+    // 按 contain 等比缩放到目标框内
+    let (orig_width, orig_height) = (img.width() as f64, img.height() as f64);
+    let scale = f64::min(
+        pad.width as f64 / orig_width,
+        pad.height as f64 / orig_height,
+    );
+    let fit_width = ((orig_width * scale).round() as u32).clamp(1, pad.width);
+    let fit_height = ((orig_height * scale).round() as u32).clamp(1, pad.height);
+    let fitted = if (fit_width, fit_height) == (img.width(), img.height()) {
+        img.into_rgba8()
+    } else {
+        resize_rgba(&img, fit_width, fit_height, FilterType::Lanczos3).into_rgba8()
+    };
+
+    // 居中叠加到背景画布上
+    let x = (pad.width - fit_width) / 2;
+    let y = (pad.height - fit_height) / 2;
+    image::imageops::overlay(&mut canvas, &fitted, x as i64, y as i64);
+    Ok(DynamicImage::ImageRgba8(canvas))
+}
+
+fn is_high_bit_depth(img: &DynamicImage) -> bool {
+    let color = img.color();
+    color.bits_per_pixel() / u16::from(color.channel_count()) > 8
+}
+
+fn resize_high_bit_depth(
+    img: &DynamicImage,
+    width: u32,
+    height: u32,
+    filter: FilterType,
+) -> DynamicImage {
+    match img {
+        DynamicImage::ImageLuma16(gray) => {
+            DynamicImage::ImageLuma16(resize(gray, width, height, filter))
+        }
+        DynamicImage::ImageRgb16(rgb) => {
+            DynamicImage::ImageRgb16(resize(rgb, width, height, filter))
+        }
+        DynamicImage::ImageRgba16(rgba) => {
+            DynamicImage::ImageRgba16(resize(rgba, width, height, filter))
+        }
+        _ => DynamicImage::ImageRgba16(resize(&img.to_rgba16(), width, height, filter)),
+    }
+}
+
+fn resize_rgba(img: &DynamicImage, width: u32, height: u32, filter: FilterType) -> DynamicImage {
+    let resized = match img {
+        DynamicImage::ImageRgba8(rgba) => resize(rgba, width, height, filter),
+        _ => resize(&img.to_rgba8(), width, height, filter),
+    };
+    DynamicImage::ImageRgba8(resized)
+}
+
+fn apply_adjustments(
+    img: DynamicImage,
+    brightness: Option<i32>,
+    contrast: Option<f32>,
+) -> DynamicImage {
+    let img = match brightness {
+        Some(value) if value != 0 => img.brighten(value),
+        _ => img,
+    };
+    match contrast {
+        Some(value) if value != 0.0 => img.adjust_contrast(value),
+        _ => img,
+    }
+}
+
+fn apply_auto_levels(img: DynamicImage, auto_levels: &AutoLevelsConfig) -> DynamicImage {
+    let mut img_rgba = img.into_rgba8();
+
+    // 统计各颜色通道直方图，完全透明的像素颜色无意义，不参与统计
+    let mut histograms = [[0u64; 256]; 3];
+    let mut count = 0u64;
+    for pixel in img_rgba.pixels().filter(|p| p[3] > 0) {
+        for c in 0..3 {
+            histograms[c][pixel[c] as usize] += 1;
+        }
+        count += 1;
+    }
+    let clip = (count as f64 * auto_levels.clip_percent as f64 / 100.0) as u64;
+
+    // 按裁剪后的最暗、最亮值构建线性映射查找表
+    let mut luts = [[0u8; 256]; 3];
+    for c in 0..3 {
+        let low = cutoff_level(histograms[c].iter().enumerate(), clip);
+        let high = cutoff_level(histograms[c].iter().enumerate().rev(), clip);
+        for (i, value) in luts[c].iter_mut().enumerate() {
+            *value = if high > low {
+                let scaled = (i as f32 - low as f32) * 255.0 / (high - low) as f32;
+                scaled.round().clamp(0.0, 255.0) as u8
+            } else {
+                // 单一色值的通道无法拉伸，保持原样
+                i as u8
+            };
+        }
+    }
+
+    for pixel in img_rgba.pixels_mut() {
+        for c in 0..3 {
+            pixel[c] = luts[c][pixel[c] as usize];
+        }
+    }
+    DynamicImage::ImageRgba8(img_rgba)
+}
+
+fn cutoff_level<'a>(mut levels: impl Iterator<Item = (usize, &'a u64)>, clip: u64) -> usize {
+    // 跳过累计数量不超过 clip 的色阶，返回第一个保留的色阶
+    let mut skipped = 0;
+    levels
+        .find(|(_, &n)| {
+            skipped += n;
+            skipped > clip
+        })
+        .map_or(0, |(level, _)| level)
+}
+
+fn apply_sepia(img: DynamicImage) -> DynamicImage {
+    let mut img_rgba = img.into_rgba8();
+    for pixel in img_rgba.pixels_mut() {
+        let [r, g, b, _] = pixel.0.map(|c| c as f32);
+        pixel[0] = (0.393 * r + 0.769 * g + 0.189 * b).min(255.0) as u8;
+        pixel[1] = (0.349 * r + 0.686 * g + 0.168 * b).min(255.0) as u8;
+        pixel[2] = (0.272 * r + 0.534 * g + 0.131 * b).min(255.0) as u8;
+    }
+    DynamicImage::ImageRgba8(img_rgba)
+}
+
+fn apply_gamma(img: DynamicImage, gamma: f32) -> DynamicImage {
+    // 线性空间 -> 显示空间：out = in ^ (1 / gamma)，预先构建查找表避免逐像素 powf
+    let mut lut = [0u8; 256];
+    for (i, value) in lut.iter_mut().enumerate() {
+        *value = ((i as f32 / 255.0).powf(1.0 / gamma) * 255.0).round() as u8;
+    }
+    let mut img_rgba = img.into_rgba8();
+    for pixel in img_rgba.pixels_mut() {
+        for c in 0..3 {
+            pixel[c] = lut[pixel[c] as usize];
+        }
+    }
+    DynamicImage::ImageRgba8(img_rgba)
+}
+
+fn apply_sharpen(img: DynamicImage, sharpen: &SharpenConfig) -> DynamicImage {
+    // USM：原图加上与高斯模糊结果之差，差值低于 threshold 的像素不变
+    img.unsharpen(sharpen.sigma, sharpen.threshold)
+}
+
+fn apply_saturation(img: DynamicImage, saturation: f32) -> DynamicImage {
+    let mut img_rgba = img.into_rgba8();
+    for pixel in img_rgba.pixels_mut() {
+        let (h, s, l) = rgb_to_hsl(pixel[0], pixel[1], pixel[2]);
+        // 饱和度限制在 [0, 1]，Alpha 保持不变
+        let (r, g, b) = hsl_to_rgb(h, (s * saturation).clamp(0.0, 1.0), l);
+        pixel[0] = r;
+        pixel[1] = g;
+        pixel[2] = b;
+    }
+    DynamicImage::ImageRgba8(img_rgba)
+}
+
+fn apply_edges(img: DynamicImage) -> DynamicImage {
+    // Sobel 梯度幅值按最大值线性归一化到 0 - 255
+    let gradients = imageproc::gradients::sobel_gradients(&img.to_luma8());
+    let max = gradients.pixels().map(|p| p[0]).max().unwrap_or(0).max(1) as u32;
+    DynamicImage::ImageLuma8(GrayImage::from_fn(
+        gradients.width(),
+        gradients.height(),
+        |x, y| Luma([(gradients.get_pixel(x, y)[0] as u32 * 255 / max) as u8]),
+    ))
+}
+
+fn apply_threshold(img: DynamicImage, threshold: &Threshold) -> DynamicImage {
+    let mut luma = img.to_luma8();
+    let level = match threshold {
+        Threshold::Value(level) => *level,
+        Threshold::Auto => imageproc::contrast::otsu_level(&luma),
+    };
+    imageproc::contrast::threshold_mut(
+        &mut luma,
+        level,
+        imageproc::contrast::ThresholdType::Binary,
+    );
+    if !img.color().has_alpha() {
+        return DynamicImage::ImageLuma8(luma);
+    }
+
+    // 保留原图透明度
+    let mut luma_alpha = img.to_luma_alpha8();
+    for (pixel, value) in luma_alpha.pixels_mut().zip(luma.pixels()) {
+        pixel[0] = value[0];
+    }
+    DynamicImage::ImageLumaA8(luma_alpha)
+}
+
+fn apply_pixelate(img: DynamicImage, pixelate: &PixelateConfig) -> Result<DynamicImage, CprError> {
+    let mut img_rgba = img.into_rgba8();
+    let [x, y, width, height] = pixelate_region(pixelate, img_rgba.width(), img_rgba.height())?;
+
+    // 以区域左上角为网格起点，每块取各通道均值后整块填充；边缘的残缺块同样处理
+    let block_size = pixelate.block_size;
+    for block_y in (y..y + height).step_by(block_size as usize) {
+        for block_x in (x..x + width).step_by(block_size as usize) {
+            let block_width = block_size.min(x + width - block_x);
+            let block_height = block_size.min(y + height - block_y);
+            let mut sum = [0u64; 4];
+            for py in block_y..block_y + block_height {
+                for px in block_x..block_x + block_width {
+                    let pixel = img_rgba.get_pixel(px, py);
+                    for c in 0..4 {
+                        sum[c] += pixel[c] as u64;
+                    }
+                }
+            }
+            let count = block_width as u64 * block_height as u64;
+            let mean = Rgba(sum.map(|total| ((total + count / 2) / count) as u8));
+            for py in block_y..block_y + block_height {
+                for px in block_x..block_x + block_width {
+                    img_rgba.put_pixel(px, py, mean);
+                }
+            }
+        }
+    }
+    Ok(DynamicImage::ImageRgba8(img_rgba))
+}
+
+// 马赛克区域的像素坐标 [x, y, width, height]，须落在图像内
+fn pixelate_region(
+    pixelate: &PixelateConfig,
+    img_width: u32,
+    img_height: u32,
+) -> Result<[u32; 4], CprError> {
+    let Some([x, y, width, height]) = pixelate.region else {
+        return Ok([0, 0, img_width, img_height]);
+    };
+    if !fits_within(x, width, img_width) || !fits_within(y, height, img_height) {
+        return Err(CprError::InvalidConfig(
+            "'pixelate.region' exceeds image bounds".into(),
+        ));
+    }
+    Ok([x, y, width, height])
+}
+
+fn apply_vignette(img: DynamicImage, vignette: &VignetteConfig) -> DynamicImage {
+    if vignette.strength == 0.0 {
+        return img;
+    }
+    let mut img_rgba = img.into_rgba8();
+    let (center_x, center_y) = (
+        img_rgba.width() as f32 / 2.0,
+        img_rgba.height() as f32 / 2.0,
+    );
+    let half_diagonal = center_x.hypot(center_y).max(f32::EPSILON);
+    for (x, y, pixel) in img_rgba.enumerate_pixels_mut() {
+        // 以像素中心到图像中心的距离除以半对角线，radius 之外按 smoothstep 平滑过渡到角落
+        let distance = (x as f32 + 0.5 - center_x).hypot(y as f32 + 0.5 - center_y) / half_diagonal;
+        let t = ((distance - vignette.radius) / (1.0 - vignette.radius)).clamp(0.0, 1.0);
+        let factor = 1.0 - vignette.strength * t * t * (3.0 - 2.0 * t);
+        for c in 0..3 {
+            pixel[c] = (pixel[c] as f32 * factor).round() as u8;
+        }
+    }
+    DynamicImage::ImageRgba8(img_rgba)
+}
+
+fn apply_overlay(img: DynamicImage, overlay: &OverlayConfig) -> DynamicImage {
+    let mut img_rgba = img.into_rgba8();
+    let (width, height) = img_rgba.dimensions();
+    let lerp = |from: Rgba<u8>, to: Rgba<u8>, pos: u32, len: u32| {
+        let t = if len > 1 {
+            pos as f32 / (len - 1) as f32
+        } else {
+            0.0
+        };
+        Rgba(
+            [0, 1, 2, 3]
+                .map(|c| (from[c] as f32 + (to[c] as f32 - from[c] as f32) * t).round() as u8),
+        )
+    };
+    for (x, y, main_pixel) in img_rgba.enumerate_pixels_mut() {
+        let color = match &overlay.fill {
+            OverlayFill::Solid(color) => *color,
+            OverlayFill::Gradient(from, to, GradientDirection::Vertical) => {
+                lerp(*from, *to, y, height)
+            }
+            OverlayFill::Gradient(from, to, GradientDirection::Horizontal) => {
+                lerp(*from, *to, x, width)
+            }
+        };
+        // 与水印相同的合成方式，保留主图 Alpha
+        let alpha_f = color[3] as f32 / 255.0 * overlay.opacity;
+        for c in 0..3 {
+            main_pixel[c] =
+                (main_pixel[c] as f32 * (1.0 - alpha_f) + color[c] as f32 * alpha_f) as u8;
+        }
+    }
+    DynamicImage::ImageRgba8(img_rgba)
+}
+
+fn rgb_to_hsl(r: u8, g: u8, b: u8) -> (f32, f32, f32) {
+    let (r, g, b) = (r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0);
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let l = (max + min) / 2.0;
+    let delta = max - min;
+    if delta == 0.0 {
+        return (0.0, 0.0, l);
+    }
+    let s = delta / (1.0 - (2.0 * l - 1.0).abs());
+    let h = if max == r {
+        ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        (b - r) / delta + 2.0
+    } else {
+        (r - g) / delta + 4.0
+    };
+    (h * 60.0, s, l)
+}
+
+fn hsl_to_rgb(h: f32, s: f32, l: f32) -> (u8, u8, u8) {
+    let c = (1.0 - (2.0 * l - 1.0).abs()) * s;
+    let x = c * (1.0 - ((h / 60.0).rem_euclid(2.0) - 1.0).abs());
+    let m = l - c / 2.0;
+    let (r, g, b) = match (h / 60.0) as u32 {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+    let to_u8 = |v: f32| ((v + m) * 255.0).round().clamp(0.0, 255.0) as u8;
+    (to_u8(r), to_u8(g), to_u8(b))
+}
+
+fn apply_watermark(
+    img: DynamicImage,
+    watermark: &WatermarkConfig,
+) -> Result<DynamicImage, CprError> {
+    let watermark_img = image::load_from_memory(&watermark.content)
+        .map_err(|e| CprError::decode("Failed to load watermark", e))?;
+
+    let source_size = (watermark_img.width(), watermark_img.height());
+    let (x, y, width, height) = place_watermark(watermark, source_size, img.width(), img.height())?;
+    // 透明度为 0 时混合结果不变，校验位置后直接返回，跳过缩放与逐像素合成
+    if watermark.opacity == 0.0 && !watermark.use_watermark_alpha {
+        return Ok(img);
+    }
+    let (resize_width, resize_height) = watermark_size(watermark, source_size, img.width());
+    let mut watermark_rgba = resize(
+        &watermark_img,
+        resize_width,
+        resize_height,
+        watermark.filter,
+    );
+    if let Some(rotation) = watermark.rotation.filter(|r| r % 360.0 != 0.0) {
+        watermark_rgba = rotate_watermark(&watermark_rgba, rotation);
+    }
+
+    // 取得缓冲区所有权原地修改；已是 RGBA8 时不会复制
+    let mut img_rgba = img.into_rgba8();
+
+    if watermark.tile {
+        // 右侧与底部的残缺水印由 blend_watermark 的边界检查裁剪
+        for tile_y in (y..img_rgba.height() as i64).step_by(height as usize) {
+            for tile_x in (x..img_rgba.width() as i64).step_by(width as usize) {
+                blend_watermark(&mut img_rgba, &watermark_rgba, tile_x, tile_y, watermark);
+            }
+        }
+    } else {
+        blend_watermark(&mut img_rgba, &watermark_rgba, x, y, watermark);
+    }
+    Ok(DynamicImage::ImageRgba8(img_rgba))
+}
+
+// 计算水印（旋转后外接框）的左上角坐标与尺寸，并检查是否落在图像内；clip 模式下坐标可超出图像
+fn place_watermark(
+    watermark: &WatermarkConfig,
+    source_size: (u32, u32),
+    img_width: u32,
+    img_height: u32,
+) -> Result<(i64, i64, u32, u32), CprError> {
+    if watermark.position.len() != 4 {
+        return Err(CprError::InvalidConfig(
+            "Watermark position must be an array of 4 numbers".into(),
+        ));
+    }
+    let [mut x, mut y, _, _] = watermark.position;
+    let (width, height) = watermark_size(watermark, source_size, img_width);
+
+    // 旋转后外接框变大，保持水印中心不变并以新尺寸做边界检查
+    let (width, height) = match watermark.rotation.filter(|r| r % 360.0 != 0.0) {
+        Some(rotation) => {
+            let (bound_width, bound_height) = rotated_bounds(width, height, rotation);
+            x -= (bound_width as i64 - width as i64) / 2;
+            y -= (bound_height as i64 - height as i64) / 2;
+            (bound_width, bound_height)
+        }
+        None => (width, height),
+    };
+
+    if let Some(anchor) = watermark.anchor.as_deref() {
+        let (anchor_x, anchor_y) = resolve_anchor(
+            anchor,
+            watermark.margin,
+            img_width,
+            img_height,
+            width,
+            height,
+        )?;
+        (x, y) = (anchor_x as i64, anchor_y as i64);
+    }
+    if watermark.tile && (width == 0 || height == 0) {
+        return Err(CprError::InvalidConfig(
+            "Tiled watermark size must be greater than 0".into(),
+        ));
+    }
+    if watermark.clip {
+        return Ok((x, y, width, height));
+    }
+
+    let (x, y) = match (u32::try_from(x), u32::try_from(y)) {
+        (Ok(x), Ok(y)) => (x, y),
+        _ => {
+            return Err(CprError::WatermarkOutOfBounds(
+                "Watermark position exceeds image bounds".into(),
+            ))
+        }
+    };
+    if watermark.tile {
+        // 平铺模式以水印尺寸为步长，起点须落在图像内
+        if x >= img_width || y >= img_height {
+            return Err(CprError::WatermarkOutOfBounds(
+                "Watermark position exceeds image bounds".into(),
+            ));
+        }
+    } else if !fits_within(x, width, img_width) || !fits_within(y, height, img_height) {
+        return Err(CprError::WatermarkOutOfBounds(
+            "Watermark position exceeds image bounds".into(),
+        ));
+    }
+    Ok((x as i64, y as i64, width, height))
+}
+
+// 旋转前的水印尺寸；按比例缩放时以主图宽度为基准并保持水印自身宽高比
+fn watermark_size(
+    watermark: &WatermarkConfig,
+    (source_width, source_height): (u32, u32),
+    img_width: u32,
+) -> (u32, u32) {
+    match watermark.scale_percent {
+        Some(percent) if source_width > 0 => {
+            let width = ((img_width as f64 * percent / 100.0).round() as u32).max(1);
+            let height =
+                ((width as f64 * source_height as f64 / source_width as f64).round() as u32).max(1);
+            (width, height)
+        }
+        _ => (watermark.position[2] as u32, watermark.position[3] as u32),
+    }
+}
+
+fn rotated_bounds(width: u32, height: u32, degrees: f32) -> (u32, u32) {
+    let (width, height) = (width as f32, height as f32);
+    let theta = degrees.to_radians();
+    let (sin, cos) = (theta.sin().abs(), theta.cos().abs());
+    // 旋转后的外接框尺寸，减去微小量避免浮点误差多出一像素
+    (
+        ((width * cos + height * sin) - 1e-3).ceil().max(1.0) as u32,
+        ((width * sin + height * cos) - 1e-3).ceil().max(1.0) as u32,
+    )
+}
+
+fn rotate_watermark(watermark_rgba: &RgbaImage, degrees: f32) -> RgbaImage {
+    let (width, height) = (
+        watermark_rgba.width() as f32,
+        watermark_rgba.height() as f32,
+    );
+    let theta = degrees.to_radians();
+    let (bound_width, bound_height) =
+        rotated_bounds(watermark_rgba.width(), watermark_rgba.height(), degrees);
+
+    // 先放到足以容纳任意角度的透明方形画布中心再旋转，最后裁出外接框
+    let side = (width.hypot(height).ceil() as u32)
+        .max(bound_width)
+        .max(bound_height);
+    let mut canvas = RgbaImage::new(side, side);
+    image::imageops::overlay(
+        &mut canvas,
+        watermark_rgba,
+        (side as i64 - watermark_rgba.width() as i64) / 2,
+        (side as i64 - watermark_rgba.height() as i64) / 2,
+    );
+    let rotated = imageproc::geometric_transformations::rotate_about_center(
+        &canvas,
+        theta,
+        imageproc::geometric_transformations::Interpolation::Bilinear,
+        Rgba([0, 0, 0, 0]),
+    );
+    image::imageops::crop_imm(
+        &rotated,
+        (side - bound_width) / 2,
+        (side - bound_height) / 2,
+        bound_width,
+        bound_height,
+    )
+    .to_image()
+}
+
+fn resolve_anchor(
+    anchor: &str,
+    margin: u32,
+    img_width: u32,
+    img_height: u32,
+    width: u32,
+    height: u32,
+) -> Result<(u32, u32), CprError> {
+    // 根据最终图像尺寸与水印尺寸计算左上角坐标
+    let exceeds =
+        || CprError::WatermarkOutOfBounds("Watermark position exceeds image bounds".into());
+    let free_x = img_width.checked_sub(width).ok_or_else(exceeds)?;
+    let free_y = img_height.checked_sub(height).ok_or_else(exceeds)?;
+    let far = |free: u32| free.checked_sub(margin).ok_or_else(exceeds);
+    match anchor {
+        "top-left" => Ok((margin, margin)),
+        "top-right" => Ok((far(free_x)?, margin)),
+        "bottom-left" => Ok((margin, far(free_y)?)),
+        "bottom-right" => Ok((far(free_x)?, far(free_y)?)),
+        "center" => Ok((free_x / 2, free_y / 2)),
+        _ => Err(CprError::InvalidConfig("'anchor' must be one of 'top-left', 'top-right', 'bottom-left', 'bottom-right' or 'center'".into())),
+    }
+}
+
+fn blend_watermark(
+    img_rgba: &mut RgbaImage,
+    watermark_rgba: &RgbaImage,
+    x: i64,
+    y: i64,
+    watermark: &WatermarkConfig,
+) {
+    // 超出图像边缘的部分直接跳过
+    for (wx, wy, watermark_pixel) in watermark_rgba.enumerate_pixels() {
+        let main_x = x + wx as i64;
+        let main_y = y + wy as i64;
+        if (0..img_rgba.width() as i64).contains(&main_x)
+            && (0..img_rgba.height() as i64).contains(&main_y)
+        {
+            let main_pixel = img_rgba.get_pixel_mut(main_x as u32, main_y as u32);
+            let alpha = if watermark.use_watermark_alpha {
+                watermark_pixel[3]
+            } else {
+                (watermark_pixel[3] as f32 * watermark.opacity as f32) as u8
+            };
+            let fade = watermark.fade.map_or(1.0, |fade| {
+                fade.factor(wx, wy, watermark_rgba.width(), watermark_rgba.height())
+            });
+            let alpha_f = alpha as f32 / 255.0 * fade;
+            for c in 0..3 {
+                let blended = watermark
+                    .blend_mode
+                    .blend(main_pixel[c], watermark_pixel[c]);
+                // 取整而非截断，保证 multiply 白色、screen 黑色等恒等混合不改变主图
+                main_pixel[c] =
+                    (main_pixel[c] as f32 * (1.0 - alpha_f) + blended * alpha_f).round() as u8;
+            }
+        }
+    }
+}
+
+fn parse_hex_color(hex: &str) -> Result<Rgba<u8>, CprError> {
+    // 支持 #RGB、#RRGGBB 与 #RRGGBBAA，'#' 可省略
+    let digits = hex.strip_prefix('#').unwrap_or(hex);
+    let invalid = || CprError::InvalidConfig(format!("Invalid hex color '{}'", hex));
+    if !digits.is_ascii() {
+        return Err(invalid());
+    }
+    let channel = |range: std::ops::Range<usize>| {
+        u8::from_str_radix(&digits[range], 16).map_err(|_| invalid())
+    };
+    match digits.len() {
+        3 => {
+            let mut rgb = [0u8; 3];
+            for (i, value) in rgb.iter_mut().enumerate() {
+                *value = channel(i..i + 1)? * 17;
+            }
+            Ok(Rgba([rgb[0], rgb[1], rgb[2], 255]))
+        }
+        6 => Ok(Rgba([channel(0..2)?, channel(2..4)?, channel(4..6)?, 255])),
+        8 => Ok(Rgba([
+            channel(0..2)?,
+            channel(2..4)?,
+            channel(4..6)?,
+            channel(6..8)?,
+        ])),
+        _ => Err(invalid()),
+    }
+}
+
+fn apply_text(img: DynamicImage, text: &TextConfig) -> Result<DynamicImage, CprError> {
+    let font = load_text_font(text)?;
+    let scale = PxScale::from(text.font_size);
+    let (x, y, width, height) = place_text(text, &font, img.width(), img.height())?;
+
+    // 先将文字渲染为覆盖率蒙版，再按颜色与 Alpha 合成，避免直接绘制改写原图 Alpha
+    let mut mask = GrayImage::new(width, height);
+    imageproc::drawing::draw_text_mut(&mut mask, Luma([255]), 0, 0, scale, &font, &text.string);
+
+    // 取得缓冲区所有权原地修改；已是 RGBA8 时不会复制
+    let mut img_rgba = img.into_rgba8();
+    let color_alpha = text.color[3] as f32 / 255.0;
+    for (tx, ty, coverage) in mask.enumerate_pixels() {
+        let main_x = x.saturating_add(tx);
+        let main_y = y.saturating_add(ty);
+        if coverage[0] > 0 && main_x < img_rgba.width() && main_y < img_rgba.height() {
+            let main_pixel = img_rgba.get_pixel_mut(main_x, main_y);
+            let alpha_f = coverage[0] as f32 / 255.0 * color_alpha;
+            for c in 0..3 {
+                main_pixel[c] =
+                    (main_pixel[c] as f32 * (1.0 - alpha_f) + text.color[c] as f32 * alpha_f) as u8;
+            }
+        }
+    }
+    Ok(DynamicImage::ImageRgba8(img_rgba))
+}
+
+fn load_text_font(text: &TextConfig) -> Result<FontArc, CprError> {
+    match &text.font {
+        Some(font) => FontArc::try_from_vec(font.clone())
+            .map_err(|e| CprError::DecodeFailed(format!("Failed to load font: {}", e))),
+        None => FontArc::try_from_slice(DEFAULT_FONT)
+            .map_err(|e| CprError::DecodeFailed(format!("Failed to load font: {}", e))),
+    }
+}
+
+// 计算文字的左上角坐标与渲染尺寸，超出图像的部分在绘制时裁掉
+fn place_text(
+    text: &TextConfig,
+    font: &FontArc,
+    img_width: u32,
+    img_height: u32,
+) -> Result<(u32, u32, u32, u32), CprError> {
+    let (width, height) =
+        imageproc::drawing::text_size(PxScale::from(text.font_size), font, &text.string);
+    let [x, y] = text.position;
+    let (x, y) = match text.anchor.as_deref() {
+        Some(anchor) => resolve_anchor(anchor, text.margin, img_width, img_height, width, height)?,
+        None => (x, y),
+    };
+    Ok((x, y, width, height))
+}
+
+fn apply_rounded_corners(
+    img: DynamicImage,
+    rounded: &RoundedCornersConfig,
+) -> Result<DynamicImage, CprError> {
+    let mut img_rgba = img.into_rgba8();
+    let (width, height) = img_rgba.dimensions();
+    let [top_left, top_right, bottom_right, bottom_left] = corner_radii(rounded, width, height)?;
+
+    // 每个角只处理各自 radius × radius 的区域；半径不超过宽、高的一半，各区域互不重叠
+    let corners = [
+        (top_left, 0, 0),
+        (top_right, width - top_right, 0),
+        (bottom_right, width - bottom_right, height - bottom_right),
+        (bottom_left, 0, height - bottom_left),
+    ];
+    for (radius, left, top) in corners {
+        if radius == 0 {
+            continue;
+        }
+        let r = radius as f32;
+        // 圆心位于区域内靠近图像中心的一角
+        let cx = if left == 0 { r } else { left as f32 };
+        let cy = if top == 0 { r } else { top as f32 };
+        for y in top..top + radius {
+            for x in left..left + radius {
+                // 以像素中心到圆心的距离估算覆盖率，实现边缘抗锯齿
+                let dx = x as f32 + 0.5 - cx;
+                let dy = y as f32 + 0.5 - cy;
+                let coverage = (r - (dx * dx + dy * dy).sqrt() + 0.5).clamp(0.0, 1.0);
+                let pixel = img_rgba.get_pixel_mut(x, y);
+                pixel[3] = (pixel[3] as f32 * coverage).round() as u8;
+            }
+        }
+    }
+    Ok(DynamicImage::ImageRgba8(img_rgba))
+}
+
+// 四角半径 [左上, 右上, 右下, 左下]；单个数值按短边一半截断，逐角指定时超出则报错
+fn corner_radii(
+    rounded: &RoundedCornersConfig,
+    width: u32,
+    height: u32,
+) -> Result<[u32; 4], CprError> {
+    match rounded.radius {
+        CornerRadius::Uniform(radius) => Ok([radius.min(width / 2).min(height / 2); 4]),
+        CornerRadius::PerCorner(radii) => {
+            if radii
+                .iter()
+                .any(|&radius| radius > width / 2 || radius > height / 2)
+            {
+                return Err(CprError::InvalidConfig(format!(
+                    "Each 'rounded_corners.radius' must not exceed half of the image width and height ({}x{})",
+                    width, height
+                )));
+            }
+            Ok(radii)
+        }
+    }
+}
+
+fn apply_circle_crop(img: DynamicImage) -> DynamicImage {
+    // 先居中裁成正方形，再遮罩出内切圆
+    let side = img.width().min(img.height());
+    let x = (img.width() - side) / 2;
+    let y = (img.height() - side) / 2;
+    let mut img_rgba = img.crop_imm(x, y, side, side).into_rgba8();
+
+    let r = side as f32 / 2.0;
+    for (x, y, pixel) in img_rgba.enumerate_pixels_mut() {
+        // 以像素中心到圆心的距离估算覆盖率，实现边缘抗锯齿
+        let dx = x as f32 + 0.5 - r;
+        let dy = y as f32 + 0.5 - r;
+        let coverage = (r - (dx * dx + dy * dy).sqrt() + 0.5).clamp(0.0, 1.0);
+        if coverage < 1.0 {
+            pixel[3] = (pixel[3] as f32 * coverage).round() as u8;
+        }
+    }
+    DynamicImage::ImageRgba8(img_rgba)
+}
+
+// 可携带透明通道的输出格式
+fn supports_alpha(format: ImageFormat) -> bool {
+    matches!(
+        format,
+        ImageFormat::Png
+            | ImageFormat::WebP
+            | ImageFormat::Avif
+            | ImageFormat::Tiff
+            | ImageFormat::Gif
+            | ImageFormat::Ico
+            | ImageFormat::Qoi
+    )
+}
+
+// encode_image 中有编码分支的格式，新增分支时须同步更新
+const OUTPUT_FORMATS: [ImageFormat; 9] = [
+    ImageFormat::Jpeg,
+    ImageFormat::Png,
+    ImageFormat::WebP,
+    ImageFormat::Gif,
+    ImageFormat::Bmp,
+    ImageFormat::Avif,
+    ImageFormat::Tiff,
+    ImageFormat::Qoi,
+    ImageFormat::Ico,
+];
+
+// scratch 为可复用的输出缓冲，清空后写入以沿用已分配的容量
+fn encode_image(
+    img: DynamicImage,
+    format: ImageFormat,
+    options: &EncodeOptions,
+    mut scratch: Vec<u8>,
+) -> Result<Vec<u8>, CprError> {
+    // 按配置拒绝会丢失透明度的 JPEG 输出，而不是静默合成
+    if format == ImageFormat::Jpeg
+        && options.on_alpha_to_jpeg == AlphaToJpeg::Error
+        && has_meaningful_alpha(&img)
+    {
+        return Err(CprError::UnsupportedOutputFormat(
+            "Image has transparent pixels that JPEG cannot store; use 'png' or 'webp' output, or set 'on_alpha_to_jpeg' to 'flatten'".into(),
+        ));
+    }
+
+    scratch.clear();
+    let mut buf = Cursor::new(scratch);
+    let quality = options.quality;
+    match format {
+        ImageFormat::Jpeg
+            if options.progressive
+                || options.jpeg_subsampling.is_some()
+                || options.icc_profile.is_some()
+                || options.dpi.is_some() =>
+        {
+            // image 的 JPEG 编码器不支持渐进式扫描、色度子采样设置、ICC 与 DPI 写入，改用 jpeg-encoder
+            let quality = quality.unwrap_or(80);
+            let img = to_jpeg_color(img, options.flatten_background, options.grayscale);
+            let color_type = match img {
+                DynamicImage::ImageLuma8(_) => jpeg_encoder::ColorType::Luma,
+                _ => jpeg_encoder::ColorType::Rgb,
+            };
+            let (width, height) = jpeg_dimensions(img.width(), img.height())?;
+            let mut encoder = jpeg_encoder::Encoder::new(&mut buf, quality);
+            encoder.set_progressive(options.progressive);
+            if let Some(sampling) = options.jpeg_subsampling {
+                encoder.set_sampling_factor(sampling);
+            }
+            if let Some(dpi) = options.dpi {
+                encoder.set_density(jpeg_encoder::PixelDensity::dpi(dpi));
+            }
+            if let Some(icc_profile) = &options.icc_profile {
+                encoder.add_icc_profile(icc_profile)?;
+            }
+            encoder.encode(img.as_bytes(), width, height, color_type)?;
+        }
+        ImageFormat::Jpeg => {
             let quality = quality.unwrap_or(80);
             let encoder = JpegEncoder::new_with_quality(&mut buf, quality);
-            img.into_rgb8().write_with_encoder(encoder)?;
+            to_jpeg_color(img, options.flatten_background, options.grayscale)
+                .write_with_encoder(encoder)?;
+        }
+        ImageFormat::Png => {
+            if options.png_palette {
+                let img_rgba = img.into_rgba8();
+                let (palette, indices) =
+                    quantize_palette(&img_rgba, options.max_colors, options.dither);
+                write_indexed_png(&mut buf, &img_rgba, &palette, &indices, options)?;
+            } else {
+                let encoder = PngEncoder::new_with_quality(
+                    &mut buf,
+                    options.png_compression,
+                    options.png_filter,
+                );
+                img.write_with_encoder(encoder)?;
+            }
+            // PngEncoder 不支持写入 ICC 与分辨率，编码后插入 iCCP / pHYs 块
+            let mut png = buf.into_inner();
+            if let Some(icc_profile) = &options.icc_profile {
+                png = insert_png_icc_profile(png, icc_profile)?;
+            }
+            if let Some(dpi) = options.dpi {
+                png = insert_png_dpi(png, dpi)?;
+            }
+            return Ok(png);
+        }
+        ImageFormat::WebP => match options.webp_lossy_quality() {
+            // image 自带的 WebP 编码器仅支持无损，有损编码交给 libwebp（不透传 ICC）
+            #[cfg(feature = "libwebp")]
+            Some(quality) => {
+                let rgba = img.into_rgba8();
+                let encoded = webp::Encoder::from_rgba(&rgba, rgba.width(), rgba.height())
+                    .encode_simple(false, quality)
+                    .map_err(|e| {
+                        CprError::EncodeFailed(format!("Failed to encode WebP: {:?}", e))
+                    })?;
+                return Ok(encoded.to_vec());
+            }
+            #[cfg(not(feature = "libwebp"))]
+            Some(_) => {
+                return Err(CprError::UnsupportedOutputFormat(
+                    "Lossy WebP output requires building with the 'libwebp' feature".into(),
+                ))
+            }
+            None => {
+                let mut encoder = WebPEncoder::new_lossless(&mut buf);
+                if let Some(icc_profile) = &options.icc_profile {
+                    encoder.set_icc_profile(icc_profile.clone())?;
+                }
+                img.write_with_encoder(encoder)?;
+            }
+        },
+        ImageFormat::Gif => {
+            // GIF 为调色板格式，由编码器负责量化
+            let mut encoder = GifEncoder::new(&mut buf);
+            let img_rgba = if options.dither {
+                dither_gif_frame(img.into_rgba8())
+            } else {
+                img.into_rgba8()
+            };
+            encoder.encode_frame(Frame::new(img_rgba))?;
+        }
+        ImageFormat::Bmp => {
+            // BMP 无质量参数；仅支持 8 位通道，其余类型先转换
+            let img = match img {
+                DynamicImage::ImageLuma8(_)
+                | DynamicImage::ImageLumaA8(_)
+                | DynamicImage::ImageRgb8(_)
+                | DynamicImage::ImageRgba8(_) => img,
+                _ if img.color().has_alpha() => DynamicImage::ImageRgba8(img.into_rgba8()),
+                _ => DynamicImage::ImageRgb8(img.into_rgb8()),
+            };
+            let encoder = BmpEncoder::new(&mut buf);
+            img.write_with_encoder(encoder)?;
+        }
+        ImageFormat::Avif => {
+            // AVIF 编码较耗时，可通过 speed 在速度与压缩率之间取舍
+            let img = match img {
+                DynamicImage::ImageRgb32F(_) => DynamicImage::ImageRgb8(img.into_rgb8()),
+                DynamicImage::ImageRgba32F(_) => DynamicImage::ImageRgba8(img.into_rgba8()),
+                _ => img,
+            };
+            let encoder =
+                AvifEncoder::new_with_speed_quality(&mut buf, options.speed, quality.unwrap_or(80));
+            img.write_with_encoder(encoder)?;
+        }
+        ImageFormat::Tiff => {
+            // image 的 TIFF 编码器不支持选择压缩方式，直接使用 tiff crate
+            match options.tiff_compression {
+                TiffCompression::None => write_tiff(&mut buf, img, Uncompressed)?,
+                TiffCompression::Lzw => write_tiff(&mut buf, img, Lzw)?,
+                TiffCompression::Deflate => write_tiff(&mut buf, img, Deflate::default())?,
+            }
+        }
+        ImageFormat::Qoi => {
+            // QOI 为无损格式，无质量参数，仅支持 8 位 RGB / RGBA
+            let img = if img.color().has_alpha() {
+                DynamicImage::ImageRgba8(img.into_rgba8())
+            } else {
+                DynamicImage::ImageRgb8(img.into_rgb8())
+            };
+            img.write_with_encoder(QoiEncoder::new(&mut buf))?;
+        }
+        ImageFormat::Ico => {
+            let img = match &options.ico_sizes {
+                // 指定多个尺寸时逐一等比缩放后打包进同一个 ICO
+                Some(sizes) => {
+                    let frames = sizes
+                        .iter()
+                        .map(|&size| img.resize(size, size, FilterType::Lanczos3).into_rgba8())
+                        .collect::<Vec<_>>();
+                    write_ico(&mut buf, &frames)?;
+                    return Ok(buf.into_inner());
+                }
+                // ICO 每边最大 256px
+                None if img.width() > ICO_MAX_SIZE || img.height() > ICO_MAX_SIZE => {
+                    if !options.ico_auto_resize {
+                        return Err(CprError::EncodeFailed(
+                            "ICO dimensions must not exceed 256 pixels".into(),
+                        ));
+                    }
+                    img.resize(ICO_MAX_SIZE, ICO_MAX_SIZE, FilterType::Lanczos3)
+                }
+                None => img,
+            };
+            write_ico(&mut buf, &[img.into_rgba8()])?;
+        }
+        _ => {
+            return Err(CprError::UnsupportedOutputFormat(
+                "Unsupported output format".into(),
+            ))
+        }
+    }
+    Ok(buf.into_inner())
+}
+
+// 按目标大小搜索 JPEG 质量时的最大编码次数
+const TARGET_SIZE_MAX_ATTEMPTS: usize = 8;
+
+// 按尺寸缩小满足 target_size_bytes 时每步的缩放比例与最短边下限
+const TARGET_SIZE_DOWNSCALE_FACTOR: f64 = 0.85;
+const TARGET_SIZE_MIN_EDGE: u32 = 16;
+// 最多缩小的步数，0.85^10 约为原尺寸的 20%
+const TARGET_SIZE_MAX_DOWNSCALE_STEPS: i32 = 10;
+
+// 按 target_size_bytes 编码：JPEG 先降低质量，开启 target_size_downscale 时再逐步缩小尺寸
+fn encode_to_target_size(
+    img: DynamicImage,
+    format: ImageFormat,
+    options: &EncodeOptions,
+    scratch: Vec<u8>,
+) -> Result<ProcessedImage, CprError> {
+    let encode = |img: &DynamicImage, scratch| -> Result<(ProcessedImage, bool), CprError> {
+        let (data, quality, fits) = match (format, options.target_size_bytes) {
+            (ImageFormat::Jpeg, Some(target)) => {
+                let (data, quality, fits) =
+                    encode_jpeg_to_target_size(img, options, target, scratch)?;
+                (data, Some(quality), fits)
+            }
+            (_, target) => {
+                let data = encode_image(img.clone(), format, options, scratch)?;
+                let fits = target.is_none_or(|target| data.len() <= target);
+                (data, None, fits)
+            }
+        };
+        let processed = ProcessedImage {
+            data,
+            width: img.width(),
+            height: img.height(),
+            format,
+            quality,
+        };
+        Ok((processed, fits))
+    };
+
+    let (mut smallest, fits) = encode(&img, scratch)?;
+    if fits {
+        return Ok(smallest);
+    }
+    if options.target_size_downscale {
+        let mut spare = Vec::new();
+        // 第 n 步从原图缩放到 FACTOR^n 倍，避免逐次重采样累积模糊；最短边低于下限或达到步数上限时停止
+        for step in 1..=TARGET_SIZE_MAX_DOWNSCALE_STEPS {
+            let scale = TARGET_SIZE_DOWNSCALE_FACTOR.powi(step);
+            let width = (img.width() as f64 * scale).round() as u32;
+            let height = (img.height() as f64 * scale).round() as u32;
+            if width.min(height) < TARGET_SIZE_MIN_EDGE {
+                break;
+            }
+            let resized = img.resize_exact(width, height, FilterType::Lanczos3);
+            // 被淘汰的编码结果作为下一步的输出缓冲
+            let (processed, fits) = encode(&resized, std::mem::take(&mut spare))?;
+            if fits {
+                return Ok(processed);
+            }
+            spare = if processed.data.len() < smallest.data.len() {
+                std::mem::replace(&mut smallest, processed).data
+            } else {
+                processed.data
+            };
+        }
+    }
+    warn("'target_size_bytes' could not be met; returning the smallest output produced");
+    Ok(smallest)
+}
+
+// 返回不超过目标大小的最高质量编码结果，都超出时返回最小的结果，并标记是否满足目标
+fn encode_jpeg_to_target_size(
+    img: &DynamicImage,
+    options: &EncodeOptions,
+    target: usize,
+    scratch: Vec<u8>,
+) -> Result<(Vec<u8>, u8, bool), CprError> {
+    // 以 quality（缺省 80）为上限，满足目标时直接返回
+    let max_quality = options.quality.unwrap_or(80);
+    let mut attempt_options = options.clone();
+    let mut encode_at = |quality, scratch| {
+        attempt_options.quality = Some(quality);
+        encode_image(img.clone(), ImageFormat::Jpeg, &attempt_options, scratch)
+    };
+    let data = encode_at(max_quality, scratch)?;
+    if data.len() <= target {
+        return Ok((data, max_quality, true));
+    }
+
+    // 二分查找不超过目标大小的最高质量
+    let (mut low, mut high) = (1, max_quality - 1);
+    let mut fitting = None;
+    let mut smallest = (data, max_quality);
+    let mut spare = Vec::new();
+    for _ in 1..TARGET_SIZE_MAX_ATTEMPTS {
+        if low > high {
+            break;
+        }
+        let quality = low + (high - low) / 2;
+        // 被淘汰的编码结果作为下一次尝试的输出缓冲
+        let data = encode_at(quality, std::mem::take(&mut spare))?;
+        if data.len() <= target {
+            if let Some((replaced, _)) = fitting.replace((data, quality)) {
+                spare = replaced;
+            }
+            low = quality + 1;
+        } else {
+            if data.len() < smallest.0.len() {
+                spare = std::mem::replace(&mut smallest, (data, quality)).0;
+            } else {
+                spare = data;
+            }
+            if quality == 1 {
+                break;
+            }
+            high = quality - 1;
+        }
+    }
+    Ok(match fitting {
+        Some((data, quality)) => (data, quality, true),
+        None => (smallest.0, smallest.1, false),
+    })
+}
+
+// 将图像量化为不超过 max_colors 种颜色的调色板，返回调色板与逐像素索引
+fn quantize_palette(
+    img_rgba: &RgbaImage,
+    max_colors: usize,
+    dither: bool,
+) -> (Vec<[u8; 4]>, Vec<u8>) {
+    // 颜色数未超过上限时直接使用原有颜色，无损
+    let mut exact: HashMap<[u8; 4], u8> = HashMap::new();
+    let mut fits = true;
+    for pixel in img_rgba.pixels() {
+        let next = exact.len();
+        if let Entry::Vacant(entry) = exact.entry(pixel.0) {
+            if next == max_colors {
+                fits = false;
+                break;
+            }
+            entry.insert(next as u8);
+        }
+    }
+    if fits {
+        let mut palette = vec![[0; 4]; exact.len()];
+        for (color, &index) in &exact {
+            palette[index as usize] = *color;
+        }
+        let indices = img_rgba.pixels().map(|p| exact[&p.0]).collect();
+        return (palette, indices);
+    }
+
+    // 否则按中位切分取各箱均值作为调色板，再将每个像素映射到最近的颜色
+    let palette: Vec<[u8; 4]> = median_cut(img_rgba.pixels().map(|p| p.0).collect(), max_colors)
+        .into_iter()
+        .filter(|pixels| !pixels.is_empty())
+        .map(|pixels| {
+            let mut sum = [0u64; 4];
+            for p in &pixels {
+                for c in 0..4 {
+                    sum[c] += p[c] as u64;
+                }
+            }
+            let count = pixels.len() as u64;
+            sum.map(|total| ((total + count / 2) / count) as u8)
+        })
+        .collect();
+    if dither {
+        let indices = dither_palette_indices(img_rgba, &palette);
+        return (palette, indices);
+    }
+    let mut nearest: HashMap<[u8; 4], u8> = HashMap::new();
+    let indices = img_rgba
+        .pixels()
+        .map(|p| {
+            *nearest
+                .entry(p.0)
+                .or_insert_with(|| nearest_palette_index(&palette, p.0))
+        })
+        .collect();
+    (palette, indices)
+}
+
+// Floyd–Steinberg 误差扩散：将每个像素的量化误差按 7/16、3/16、5/16、1/16 分摊给右侧与下一行的相邻像素
+fn dither_palette_indices(img_rgba: &RgbaImage, palette: &[[u8; 4]]) -> Vec<u8> {
+    let (width, height) = img_rgba.dimensions();
+    let width = width as usize;
+    let mut indices = Vec::with_capacity(width * height as usize);
+    let mut nearest: HashMap<[u8; 4], u8> = HashMap::new();
+    // 当前行与下一行的累积误差，左右各留一列避免边界判断
+    let mut current = vec![[0f32; 4]; width + 2];
+    let mut next = vec![[0f32; 4]; width + 2];
+    for y in 0..height {
+        for x in 0..width {
+            let pixel = img_rgba.get_pixel(x as u32, y).0;
+            let mut color = [0u8; 4];
+            for c in 0..4 {
+                color[c] = (pixel[c] as f32 + current[x + 1][c])
+                    .round()
+                    .clamp(0.0, 255.0) as u8;
+            }
+            let index = *nearest
+                .entry(color)
+                .or_insert_with(|| nearest_palette_index(palette, color));
+            indices.push(index);
+
+            let chosen = palette[index as usize];
+            for c in 0..4 {
+                let error = color[c] as f32 - chosen[c] as f32;
+                current[x + 2][c] += error * 7.0 / 16.0;
+                next[x][c] += error * 3.0 / 16.0;
+                next[x + 1][c] += error * 5.0 / 16.0;
+                next[x + 2][c] += error / 16.0;
+            }
+        }
+        std::mem::swap(&mut current, &mut next);
+        next.fill([0.0; 4]);
+    }
+    indices
+}
+
+// GIF 编码器在颜色不超过 256 种时使用精确调色板，预先抖动量化即可替代其内部的 NeuQuant
+fn dither_gif_frame(mut img_rgba: RgbaImage) -> RgbaImage {
+    // GIF 仅支持全透明或不透明，与编码器一致地二值化 alpha，避免调色板浪费在半透明色上
+    for pixel in img_rgba.pixels_mut() {
+        if pixel[3] != 0 {
+            pixel[3] = 255;
+        }
+    }
+    let (palette, indices) = quantize_palette(&img_rgba, 256, true);
+    let width = img_rgba.width();
+    RgbaImage::from_fn(width, img_rgba.height(), |x, y| {
+        Rgba(palette[indices[(y * width + x) as usize] as usize])
+    })
+}
+
+fn nearest_palette_index(palette: &[[u8; 4]], color: [u8; 4]) -> u8 {
+    palette
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, entry)| {
+            (0..4)
+                .map(|c| {
+                    let diff = entry[c] as i32 - color[c] as i32;
+                    diff * diff
+                })
+                .sum::<i32>()
+        })
+        .map_or(0, |(index, _)| index as u8)
+}
+
+fn write_indexed_png<W: Write>(
+    w: W,
+    img_rgba: &RgbaImage,
+    palette: &[[u8; 4]],
+    indices: &[u8],
+    options: &EncodeOptions,
+) -> Result<(), CprError> {
+    // 按调色板大小选择最小位深，多个索引打包进同一字节
+    let (width, height) = img_rgba.dimensions();
+    let (depth, bits) = match palette.len() {
+        0..=2 => (png::BitDepth::One, 1),
+        3..=4 => (png::BitDepth::Two, 2),
+        5..=16 => (png::BitDepth::Four, 4),
+        _ => (png::BitDepth::Eight, 8),
+    };
+    let row_bytes = (width as usize * bits).div_ceil(8);
+    let mut data = vec![0u8; row_bytes * height as usize];
+    for (i, &index) in indices.iter().enumerate() {
+        let (y, x) = (i / width as usize, i % width as usize);
+        let bit = x * bits;
+        data[y * row_bytes + bit / 8] |= index << (8 - bits - bit % 8);
+    }
+
+    let mut encoder = png::Encoder::new(w, width, height);
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(depth);
+    encoder.set_palette(
+        palette
+            .iter()
+            .flat_map(|c| [c[0], c[1], c[2]])
+            .collect::<Vec<_>>(),
+    );
+    // 有透明颜色时写入 tRNS，省略末尾完全不透明的项
+    let trns_len = palette
+        .iter()
+        .rposition(|c| c[3] < 255)
+        .map_or(0, |i| i + 1);
+    if trns_len > 0 {
+        encoder.set_trns(palette[..trns_len].iter().map(|c| c[3]).collect::<Vec<_>>());
+    }
+    encoder.set_compression(match options.png_compression {
+        CompressionType::Default => png::Compression::Default,
+        CompressionType::Best => png::Compression::Best,
+        _ => png::Compression::Fast,
+    });
+    match options.png_filter {
+        PngFilterType::NoFilter => encoder.set_filter(png::FilterType::NoFilter),
+        PngFilterType::Sub => encoder.set_filter(png::FilterType::Sub),
+        PngFilterType::Up => encoder.set_filter(png::FilterType::Up),
+        PngFilterType::Avg => encoder.set_filter(png::FilterType::Avg),
+        PngFilterType::Paeth => encoder.set_filter(png::FilterType::Paeth),
+        _ => encoder.set_adaptive_filter(png::AdaptiveFilterType::Adaptive),
+    }
+    let mut writer = encoder
+        .write_header()
+        .map_err(|e| CprError::EncodeFailed(format!("Failed to encode PNG: {}", e)))?;
+    writer
+        .write_image_data(&data)
+        .map_err(|e| CprError::EncodeFailed(format!("Failed to encode PNG: {}", e)))?;
+    writer
+        .finish()
+        .map_err(|e| CprError::EncodeFailed(format!("Failed to encode PNG: {}", e)))
+}
+
+// ICO 单帧的最大边长
+const ICO_MAX_SIZE: u32 = 256;
+
+fn write_ico<W: Write>(w: W, frames: &[RgbaImage]) -> Result<(), CprError> {
+    let frames = frames
+        .iter()
+        .map(|frame| {
+            IcoFrame::as_png(
+                frame.as_raw(),
+                frame.width(),
+                frame.height(),
+                ExtendedColorType::Rgba8,
+            )
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    IcoEncoder::new(w).encode_images(&frames)?;
+    Ok(())
+}
+
+// 元数据处理：解码后重新编码的各路径均不会写入 EXIF / ICC / XMP。
+// strip_metadata 为 false 时尝试透传输入的 ICC 配置文件，支持情况如下：
+// - JPEG：通过 jpeg-encoder 写入 APP2 段
+// - PNG：编码后插入 iCCP 块
+// - WebP：仅无损编码支持，有损编码（libwebp）忽略
+// - GIF / BMP / AVIF / TIFF：不支持，忽略
+fn read_icc_profile(input_data: &[u8], format: ImageFormat) -> Option<Vec<u8>> {
+    let mut decoder = image::ImageReader::with_format(Cursor::new(input_data), format)
+        .into_decoder()
+        .ok()?;
+    // CMYK 配置文件描述的是解码前的色彩空间，附加到 RGB 输出会导致颜色错误
+    decoder
+        .icc_profile()
+        .ok()
+        .flatten()
+        .filter(|icc_profile| icc_profile.get(16..20) != Some(b"CMYK".as_slice()))
+}
+
+fn insert_png_icc_profile(png: Vec<u8>, icc_profile: &[u8]) -> Result<Vec<u8>, CprError> {
+    // iCCP 数据：配置文件名 + NUL + 压缩方式 (0) + zlib 压缩后的配置文件
+    let mut data = b"ICC profile\0\0".to_vec();
+    let mut zlib = flate2::write::ZlibEncoder::new(&mut data, flate2::Compression::default());
+    zlib.write_all(icc_profile)?;
+    zlib.finish()?;
+    insert_png_chunk(png, b"iCCP", &data)
+}
+
+fn insert_png_chunk(png: Vec<u8>, chunk_type: &[u8; 4], data: &[u8]) -> Result<Vec<u8>, CprError> {
+    // 签名 (8) + IHDR 块 (4 长度 + 4 类型 + 13 数据 + 4 CRC)，新块紧随 IHDR 之后
+    const IHDR_END: usize = 8 + 4 + 4 + 13 + 4;
+    if png.len() < IHDR_END || &png[12..16] != b"IHDR" {
+        return Err(CprError::EncodeFailed("Invalid PNG data".into()));
+    }
+    let length = u32::try_from(data.len())
+        .map_err(|_| CprError::EncodeFailed("PNG chunk too large".into()))?;
+    let mut crc = crc32fast::Hasher::new();
+    crc.update(chunk_type);
+    crc.update(data);
+
+    let mut out = Vec::with_capacity(png.len() + data.len() + 12);
+    out.extend_from_slice(&png[..IHDR_END]);
+    out.extend_from_slice(&length.to_be_bytes());
+    out.extend_from_slice(chunk_type);
+    out.extend_from_slice(data);
+    out.extend_from_slice(&crc.finalize().to_be_bytes());
+    out.extend_from_slice(&png[IHDR_END..]);
+    Ok(out)
+}
+
+fn insert_png_dpi(png: Vec<u8>, dpi: u16) -> Result<Vec<u8>, CprError> {
+    // pHYs 数据：X / Y 方向每米像素数 + 单位 (1 = 米)
+    let pixels_per_meter = (dpi as f64 / 0.0254).round() as u32;
+    let mut data = Vec::with_capacity(9);
+    data.extend_from_slice(&pixels_per_meter.to_be_bytes());
+    data.extend_from_slice(&pixels_per_meter.to_be_bytes());
+    data.push(1);
+    insert_png_chunk(png, b"pHYs", &data)
+}
+
+fn write_tiff<D: Compression>(
+    buf: &mut Cursor<Vec<u8>>,
+    img: DynamicImage,
+    compression: D,
+) -> Result<(), CprError> {
+    let mut encoder = tiff::encoder::TiffEncoder::new(buf)?;
+    let (width, height) = (img.width(), img.height());
+    match img {
+        DynamicImage::ImageLuma8(gray) => encoder
+            .write_image_with_compression::<colortype::Gray8, _>(
+                width,
+                height,
+                compression,
+                &gray,
+            )?,
+        DynamicImage::ImageLuma16(gray) => encoder
+            .write_image_with_compression::<colortype::Gray16, _>(
+                width,
+                height,
+                compression,
+                &gray,
+            )?,
+        DynamicImage::ImageRgb16(rgb) => encoder
+            .write_image_with_compression::<colortype::RGB16, _>(
+                width,
+                height,
+                compression,
+                &rgb,
+            )?,
+        DynamicImage::ImageLumaA16(_) | DynamicImage::ImageRgba16(_) => {
+            let rgba = img.into_rgba16();
+            encoder.write_image_with_compression::<colortype::RGBA16, _>(
+                width,
+                height,
+                compression,
+                &rgba,
+            )?
+        }
+        _ if img.color().has_alpha() => {
+            let rgba = img.into_rgba8();
+            encoder.write_image_with_compression::<colortype::RGBA8, _>(
+                width,
+                height,
+                compression,
+                &rgba,
+            )?
+        }
+        _ => {
+            let rgb = img.into_rgb8();
+            encoder.write_image_with_compression::<colortype::RGB8, _>(
+                width,
+                height,
+                compression,
+                &rgb,
+            )?
+        }
+    }
+    Ok(())
+}
+
+fn has_meaningful_alpha(img: &DynamicImage) -> bool {
+    // 带 Alpha 通道但完全不透明的图像合成后不会有损失
+    match img {
+        DynamicImage::ImageRgba8(buffer) => buffer.pixels().any(|p| p[3] < 255),
+        DynamicImage::ImageLumaA8(buffer) => buffer.pixels().any(|p| p[1] < 255),
+        _ if img.color().has_alpha() => img.to_rgba8().pixels().any(|p| p[3] < 255),
+        _ => false,
+    }
+}
+
+fn to_jpeg_color(img: DynamicImage, background: Rgba<u8>, grayscale: bool) -> DynamicImage {
+    // JPEG 不支持 Alpha 通道，带透明度的图像先合成到背景色上；灰度图像保持单通道以减小体积
+    let rgb = match img {
+        DynamicImage::ImageLuma8(_) => return img,
+        DynamicImage::ImageLuma16(_) => return DynamicImage::ImageLuma8(img.into_luma8()),
+        _ if !img.color().has_alpha() => img.into_rgb8(),
+        _ => flatten_alpha(img, background),
+    };
+    // grayscale 之后的水印等步骤会把灰度图转为 RGBA，仅此时按内容判断是否仍为灰度；遇到第一个彩色像素即停止扫描
+    if grayscale
+        && rgb
+            .pixels()
+            .all(|pixel| pixel[0] == pixel[1] && pixel[1] == pixel[2])
+    {
+        DynamicImage::ImageLuma8(DynamicImage::ImageRgb8(rgb).into_luma8())
+    } else {
+        DynamicImage::ImageRgb8(rgb)
+    }
+}
+
+fn flatten_alpha(img: DynamicImage, background: Rgba<u8>) -> RgbImage {
+    let img_rgba = img.into_rgba8();
+    RgbImage::from_fn(img_rgba.width(), img_rgba.height(), |x, y| {
+        let pixel = img_rgba.get_pixel(x, y);
+        let alpha = pixel[3] as f32 / 255.0;
+        Rgb([0, 1, 2].map(|c| {
+            (pixel[c] as f32 * alpha + background[c] as f32 * (1.0 - alpha)).round() as u8
+        }))
+    })
+}
+
+fn jpeg_dimensions(width: u32, height: u32) -> Result<(u16, u16), CprError> {
+    match (u16::try_from(width), u16::try_from(height)) {
+        (Ok(width), Ok(height)) => Ok((width, height)),
+        _ => Err(CprError::EncodeFailed(
+            "JPEG dimensions must not exceed 65535 pixels".into(),
+        )),
+    }
+}
+
+fn resolve_input_format(input_data: &[u8], format: Option<&str>) -> Result<ImageFormat, CprError> {
+    // 未指定输入格式时根据文件头魔数识别
+    // CUR 与 ICO 结构相同，仅类型字段为 2，交给 ICO 解码器处理
+    match format {
+        Some(format) if format.eq_ignore_ascii_case("cur") => Ok(ImageFormat::Ico),
+        Some(format) => ImageFormat::from_extension(format)
+            .ok_or(CprError::InvalidInputFormat("Invalid input format".into())),
+        None if input_data.starts_with(&[0, 0, 2, 0]) => Ok(ImageFormat::Ico),
+        None => image::guess_format(input_data)
+            .map_err(|_| CprError::InvalidInputFormat("Unable to detect input format".into())),
+    }
+}
+
+fn check_input_limits(
+    input_data: &[u8],
+    format: ImageFormat,
+    max_pixels: u64,
+    max_input_bytes: Option<usize>,
+) -> Result<(u32, u32), CprError> {
+    if let Some(max_input_bytes) = max_input_bytes {
+        if input_data.len() > max_input_bytes {
+            return Err(CprError::InputTooLarge(format!(
+                "Input is {} bytes, exceeding 'max_input_bytes' of {}",
+                input_data.len(),
+                max_input_bytes
+            )));
+        }
+    }
+
+    // 仅读取文件头获取尺寸，在解码分配像素缓冲之前拦截
+    let (width, height) = image::ImageReader::with_format(Cursor::new(input_data), format)
+        .into_dimensions()
+        .map_err(|e| CprError::decode("Failed to decode input image", e))?;
+    let pixels = width as u64 * height as u64;
+    if pixels > max_pixels {
+        return Err(CprError::InputTooLarge(format!(
+            "Input is {}x{} ({} pixels), exceeding 'max_pixels' of {}",
+            width, height, pixels, max_pixels
+        )));
+    }
+    Ok((width, height))
+}
+
+fn decode_image(input_data: &[u8], format: ImageFormat) -> Result<DynamicImage, CprError> {
+    image::load_from_memory_with_format(input_data, format)
+        .map_err(|e| CprError::decode("Failed to decode input image", e))
+}
+
+fn decode_ico_entry(input_data: &[u8], index: u32) -> Result<DynamicImage, CprError> {
+    let malformed =
+        || CprError::DecodeFailed("Failed to decode input image: malformed ICO directory".into());
+    let read_u16 = |offset: usize| {
+        input_data
+            .get(offset..offset + 2)
+            .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+    };
+    let read_u32 = |offset: usize| {
+        input_data
+            .get(offset..offset + 4)
+            .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    };
+
+    // 目录头 6 字节（保留、类型、数量），随后每个条目 16 字节，末 8 字节为数据长度与偏移
+    let count = read_u16(4).ok_or_else(malformed)?;
+    if index >= count as u32 {
+        return Err(CprError::InvalidConfig(format!(
+            "'ico_index' {} is out of range; icon has {} images",
+            index, count
+        )));
+    }
+    let entry_offset = 6 + index as usize * 16;
+    let entry = input_data
+        .get(entry_offset..entry_offset + 16)
+        .ok_or_else(malformed)?;
+    let data_size = read_u32(entry_offset + 8).ok_or_else(malformed)? as usize;
+    let data_offset = read_u32(entry_offset + 12).ok_or_else(malformed)? as usize;
+    let data = data_offset
+        .checked_add(data_size)
+        .and_then(|end| input_data.get(data_offset..end))
+        .ok_or_else(malformed)?;
+
+    // 仅保留选中条目重建单图 ICO，解码器便不会再按尺寸挑选
+    let mut single = Vec::with_capacity(22 + data.len());
+    single.extend_from_slice(&[0, 0, 1, 0, 1, 0]);
+    single.extend_from_slice(&entry[..12]);
+    single.extend_from_slice(&22u32.to_le_bytes());
+    single.extend_from_slice(data);
+    decode_image(&single, ImageFormat::Ico)
+}
+
+fn decode_jpeg_scaled(input_data: &[u8], config: &ImageConfig) -> Option<DynamicImage> {
+    // 像素裁剪以原图坐标为准，按比例裁剪和去边后尺寸未知，调整过阶段顺序时水印也可能按原图尺寸定位，此时不能缩小解码
+    let size = config.size.as_ref()?;
+    if config.crop.is_some()
+        || config.crop_aspect.is_some()
+        || config.trim.is_some()
+        || config.operation_order != DEFAULT_OPERATION_ORDER
+    {
+        return None;
+    }
+
+    let mut decoder = jpeg_decoder::Decoder::new(Cursor::new(input_data));
+    decoder.read_info().ok()?;
+    let info = decoder.info()?;
+    let (orig_width, orig_height) = (info.width as f64, info.height as f64);
+
+    // 按与 resolve_size 相同的规则估算目标尺寸，并考虑旋转后宽高互换，取所需的最大缩放比
+    let (target_width, target_height) = match (size.width, size.height) {
+        (Some(width), Some(height)) => (width as f64, height as f64),
+        (Some(width), None) => (width as f64, orig_height * width as f64 / orig_width),
+        (None, Some(height)) => (orig_width * height as f64 / orig_height, height as f64),
+        // 仅指定 scale 时 resolve_size 按解码后尺寸再次缩放，缩小解码会导致缩放两次
+        (None, None) => return None,
+    };
+    let required_scale = [
+        target_width / orig_width,
+        target_height / orig_height,
+        target_width / orig_height,
+        target_height / orig_width,
+    ]
+    .into_iter()
+    .fold(0.0, f64::max);
+
+    // 解码器支持 1/8、1/4、1/2 缩放，取不小于所需尺寸的最大缩小倍数
+    let factor = [8.0, 4.0, 2.0]
+        .into_iter()
+        .find(|&factor| 1.0 / factor >= required_scale)?;
+    decoder
+        .scale(
+            (orig_width / factor).ceil() as u16,
+            (orig_height / factor).ceil() as u16,
+        )
+        .ok()?;
+    let pixels = decoder.decode().ok()?;
+    let info = decoder.info()?;
+    let (width, height) = (info.width as u32, info.height as u32);
+    match info.pixel_format {
+        jpeg_decoder::PixelFormat::L8 => {
+            GrayImage::from_raw(width, height, pixels).map(DynamicImage::ImageLuma8)
+        }
+        jpeg_decoder::PixelFormat::RGB24 => {
+            RgbImage::from_raw(width, height, pixels).map(DynamicImage::ImageRgb8)
+        }
+        jpeg_decoder::PixelFormat::CMYK32 => {
+            RgbImage::from_raw(width, height, cmyk_to_rgb(&pixels)).map(DynamicImage::ImageRgb8)
+        }
+        // 其他像素格式回退到常规解码
+        _ => None,
+    }
+}
+
+fn cmyk_to_rgb(pixels: &[u8]) -> Vec<u8> {
+    // 解码器已处理 Adobe 反相与 YCCK 转换，此处按与常规解码相同的朴素公式换算
+    pixels
+        .chunks_exact(4)
+        .flat_map(|p| {
+            let k = 255 - p[3] as u32;
+            [0, 1, 2].map(|c| (((255 - p[c] as u32) * k + 127) / 255) as u8)
+        })
+        .collect()
+}
+
+fn decode_frame(
+    input_data: &[u8],
+    format: ImageFormat,
+    index: u32,
+) -> Result<DynamicImage, CprError> {
+    // 非 GIF 输入视为单帧
+    if format != ImageFormat::Gif {
+        return match index {
+            0 => decode_image(input_data, format),
+            _ => Err(CprError::InvalidConfig(format!(
+                "'frame_index' {} is out of range; image has 1 frame",
+                index
+            ))),
+        };
+    }
+
+    let decoder = GifDecoder::new(Cursor::new(input_data))
+        .map_err(|e| CprError::decode("Failed to decode input image", e))?;
+    let mut frame_count = 0;
+    for frame in decoder.into_frames() {
+        let frame = frame.map_err(|e| CprError::decode("Failed to decode GIF frame", e))?;
+        if frame_count == index {
+            return Ok(DynamicImage::ImageRgba8(frame.into_buffer()));
+        }
+        frame_count += 1;
+    }
+    Err(CprError::InvalidConfig(format!(
+        "'frame_index' {} is out of range; image has {} frames",
+        index, frame_count
+    )))
+}
+
+// 中位切分的分箱数，取像素最多的一箱作为主色
+const DOMINANT_COLOR_BOXES: usize = 8;
+
+fn median_cut<const N: usize>(pixels: Vec<[u8; N]>, max_boxes: usize) -> Vec<Vec<[u8; N]>> {
+    let mut boxes = vec![pixels];
+    while boxes.len() < max_boxes {
+        // 选择通道跨度最大的箱子，沿该通道在中位数处一分为二
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, pixels)| pixels.len() > 1)
+            .map(|(i, pixels)| {
+                let (channel, range) = (0..N)
+                    .map(|c| {
+                        let (min, max) = pixels.iter().fold((255, 0), |(min, max), p| {
+                            (u8::min(min, p[c]), u8::max(max, p[c]))
+                        });
+                        (c, max - min)
+                    })
+                    .max_by_key(|&(_, range)| range)
+                    .unwrap_or((0, 0));
+                (i, channel, range)
+            })
+            .max_by_key(|&(_, _, range)| range);
+        let Some((i, channel, range)) = widest else {
+            break;
+        };
+        if range == 0 {
+            break;
+        }
+        let mut pixels = boxes.swap_remove(i);
+        pixels.sort_unstable_by_key(|p| p[channel]);
+        let upper = pixels.split_off(pixels.len() / 2);
+        boxes.push(pixels);
+        boxes.push(upper);
+    }
+    boxes
+}
+
+struct ProcessedImage {
+    data: Vec<u8>,
+    width: u32,
+    height: u32,
+    format: ImageFormat,
+    quality: Option<u8>, // 按 target_size_bytes 选出的 JPEG 质量
+}
+
+// 没有任何变换与编码参数、输出格式与输入相同时直接返回原始字节，避免 JPEG 等有损格式重复压缩
+// strip_metadata 时仅在输入含可去除的元数据时才须重新编码；GIF、WebP、TIFF、ICO 可能含多帧或多图，解码只取其一，不能直通
+fn passes_through(
+    input_data: &[u8],
+    format: ImageFormat,
+    output_format: ImageFormat,
+    config: &ImageConfig,
+) -> bool {
+    let encode = &config.encode;
+    let transforms = config.auto_orient
+        || config.frame_index.is_some()
+        || config.trim.is_some()
+        || config.denoise.is_some()
+        || config.chroma_key.is_some()
+        || config.crop.is_some()
+        || config.crop_aspect.is_some()
+        || config.rotate.is_some()
+        || config.flip_horizontal
+        || config.flip_vertical
+        || config.size.is_some()
+        || config.thumbnail.is_some()
+        || config.pad.is_some()
+        || config.grayscale
+        || config.auto_levels.is_some()
+        || config.brightness.is_some()
+        || config.contrast.is_some()
+        || config.blur.is_some()
+        || config.sharpen.is_some()
+        || config.sepia
+        || config.invert
+        || config.gamma.is_some()
+        || config.saturation.is_some()
+        || config.edges
+        || config.threshold.is_some()
+        || config.pixelate.is_some()
+        || config.overlay.is_some()
+        || config.vignette.is_some()
+        || !config.watermarks.is_empty()
+        || config.text.is_some()
+        || config.rounded_corners.is_some()
+        || config.circle_crop;
+    let encode_options = encode.quality.is_some()
+        || encode.target_size_bytes.is_some()
+        || encode.progressive
+        || encode.jpeg_subsampling.is_some()
+        || encode.dpi.is_some()
+        || encode.png_palette
+        || encode.png_compression != CompressionType::Best
+        || encode.png_filter != PngFilterType::Paeth;
+    !config.force_reencode
+        && (!encode.strip_metadata || !has_strippable_metadata(input_data, format))
+        && !transforms
+        && !encode_options
+        && format == output_format
+        && matches!(
+            format,
+            ImageFormat::Jpeg | ImageFormat::Png | ImageFormat::Bmp | ImageFormat::Qoi
+        )
+        // CMYK JPEG 需转换为 RGB 输出
+        && !(format == ImageFormat::Jpeg && is_cmyk_jpeg(input_data))
+}
+
+// 是否含有 strip_metadata 会去除的元数据（EXIF / ICC / XMP / 注释 / 文本等），无法解析时视为含有
+fn has_strippable_metadata(input_data: &[u8], format: ImageFormat) -> bool {
+    match format {
+        ImageFormat::Jpeg => jpeg_has_metadata(input_data).unwrap_or(true),
+        ImageFormat::Png => png_has_metadata(input_data).unwrap_or(true),
+        ImageFormat::Bmp | ImageFormat::Qoi => false,
+        _ => true,
+    }
+}
+
+fn jpeg_has_metadata(input_data: &[u8]) -> Option<bool> {
+    // 逐段遍历到 SOS；APP0 (JFIF) 与 APP14 (Adobe 颜色变换) 影响解码，不算元数据
+    let mut offset = 2;
+    loop {
+        let marker = *input_data.get(offset + 1)?;
+        if input_data[offset] != 0xFF {
+            return None;
+        }
+        match marker {
+            // 填充字节与不带长度的独立标记
+            0xFF => {
+                offset += 1;
+                continue;
+            }
+            0x01 | 0xD0..=0xD7 => {
+                offset += 2;
+                continue;
+            }
+            0xDA => return Some(false),
+            0xE1..=0xED | 0xEF | 0xFE => return Some(true),
+            _ => {}
+        }
+        let length =
+            u16::from_be_bytes([*input_data.get(offset + 2)?, *input_data.get(offset + 3)?]);
+        offset += 2 + length as usize;
+    }
+}
+
+fn png_has_metadata(input_data: &[u8]) -> Option<bool> {
+    // 逐块遍历到 IEND：长度 (4) + 类型 (4) + 数据 + CRC (4)
+    let mut offset = 8;
+    loop {
+        let length = u32::from_be_bytes(input_data.get(offset..offset + 4)?.try_into().ok()?);
+        match input_data.get(offset + 4..offset + 8)? {
+            b"IEND" => return Some(false),
+            b"eXIf" | b"iCCP" | b"tEXt" | b"zTXt" | b"iTXt" | b"tIME" => return Some(true),
+            _ => {}
+        }
+        offset = offset.checked_add(12 + length as usize)?;
+    }
+}
+
+fn is_cmyk_jpeg(input_data: &[u8]) -> bool {
+    let mut decoder = jpeg_decoder::Decoder::new(Cursor::new(input_data));
+    decoder.read_info().is_ok()
+        && decoder
+            .info()
+            .is_some_and(|info| info.pixel_format == jpeg_decoder::PixelFormat::CMYK32)
+}
+
+fn resolve_output_format(
+    config: &ImageConfig,
+    format: ImageFormat,
+) -> Result<ImageFormat, CprError> {
+    // 圆角、圆形裁剪等遮罩需要透明通道：显式指定不支持透明的格式时报错，未指定时改用 PNG
+    let needs_alpha =
+        config.rounded_corners.is_some() || config.circle_crop || config.chroma_key.is_some();
+    match &config.output_format {
+        Some(output_format) => {
+            let output_format = ImageFormat::from_extension(output_format).ok_or(
+                CprError::UnsupportedOutputFormat("Invalid output format".into()),
+            )?;
+            if needs_alpha && !supports_alpha(output_format) {
+                return Err(CprError::UnsupportedOutputFormat(
+                    "Output format must support transparency when masking corners or circles or applying a chroma key"
+                        .into(),
+                ));
+            }
+            Ok(output_format)
+        }
+        None if needs_alpha && !supports_alpha(format) => Ok(ImageFormat::Png),
+        None => Ok(format),
+    }
+}
+
+fn transform_image(
+    img: DynamicImage,
+    input_data: &[u8],
+    config: &ImageConfig,
+) -> Result<DynamicImage, CprError> {
+    let high_bit_depth = is_high_bit_depth(&img);
+    let img = transform_head(img, input_data, config)?;
+    transform_tail(img, config, high_bit_depth)
+}
+
+// 变换的前半段：方向校正到 operation_order 第二个位置的阶段为止
+fn transform_head(
+    mut img: DynamicImage,
+    input_data: &[u8],
+    config: &ImageConfig,
+) -> Result<DynamicImage, CprError> {
+    // 按 EXIF 方向校正，须在其他变换之前
+    if config.auto_orient {
+        img = apply_auto_orient(img, input_data);
+    }
+
+    // 去除纯色边框，须在其他变换之前
+    if let Some(trim) = &config.trim {
+        img = apply_trim(img, trim)?;
+    }
+
+    // 中值滤波降噪，须在缩放之前处理原始噪点
+    if let Some(denoise) = &config.denoise {
+        img = apply_denoise(img, denoise);
+    }
+
+    // 抠除纯色背景，须在颜色滤镜之前以按原始颜色匹配
+    if let Some(chroma_key) = &config.chroma_key {
+        img = apply_chroma_key(img, chroma_key);
+    }
+
+    // 裁剪、缩放、水印依次占据默认顺序中的三个位置，按 operation_order 决定各位置执行的阶段
+    let [first, second] = [0, 1].map(|i| config.operation_order[i]);
+    img = apply_stage(img, first, config)?;
+
+    // 应用旋转
+    if let Some(degrees) = config.rotate {
+        img = apply_rotate(img, degrees)?;
+    }
+
+    // 应用翻转
+    if config.flip_horizontal || config.flip_vertical {
+        img = apply_flip(img, config.flip_horizontal, config.flip_vertical);
+    }
+
+    apply_stage(img, second, config)
+}
+
+// 变换的后半段：缩略图、填充、颜色滤镜、第三个位置的阶段与遮罩
+fn transform_tail(
+    mut img: DynamicImage,
+    config: &ImageConfig,
+    high_bit_depth: bool,
+) -> Result<DynamicImage, CprError> {
+    // 生成缩略图
+    if let Some(thumbnail) = &config.thumbnail {
+        img = apply_thumbnail(img, thumbnail);
+    }
+
+    // 填充到固定尺寸
+    if let Some(pad) = &config.pad {
+        img = apply_pad(img, pad)?;
+    }
+
+    config.deadline.check("applying filters")?;
+
+    // 转为灰度
+    if config.grayscale {
+        img = img.grayscale();
+    }
+
+    // 自动色阶，先拉伸直方图再应用手动亮度与对比度
+    if let Some(auto_levels) = &config.auto_levels {
+        img = apply_auto_levels(img, auto_levels);
+    }
+
+    // 调整亮度与对比度
+    if config.brightness.is_some() || config.contrast.is_some() {
+        img = apply_adjustments(img, config.brightness, config.contrast);
+    }
+
+    // 高斯模糊（可配合极小尺寸生成 LQIP 占位图）
+    if let Some(sigma) = config.blur {
+        if sigma > 0.0 {
+            img = img.blur(sigma);
+        }
+    }
+
+    // 锐化
+    if let Some(sharpen) = &config.sharpen {
+        img = apply_sharpen(img, sharpen);
+    }
+
+    // 复古褐色滤镜
+    if config.sepia {
+        img = apply_sepia(img);
+    }
+
+    // 反色，须在水印之前以保持水印原色
+    if config.invert {
+        img.invert();
+    }
+
+    // 伽马校正
+    if let Some(gamma) = config.gamma {
+        img = apply_gamma(img, gamma);
+    }
+
+    // 饱和度调整
+    if let Some(saturation) = config.saturation {
+        img = apply_saturation(img, saturation);
+    }
+
+    // 边缘检测，输出单通道边缘强度图，可再经 threshold 二值化
+    if config.edges {
+        img = apply_edges(img);
+    }
+
+    // 二值化，输出单通道黑白图
+    if let Some(threshold) = &config.threshold {
+        img = apply_threshold(img, threshold);
+    }
+
+    // 马赛克，须在水印之前以免遮挡水印
+    if let Some(pixelate) = &config.pixelate {
+        img = apply_pixelate(img, pixelate)?;
+    }
+
+    // 叠加纯色或渐变，用于压暗背景以突出其上的文字
+    if let Some(overlay) = &config.overlay {
+        img = apply_overlay(img, overlay);
+    }
+
+    // 暗角，须在水印之前以保持水印原色
+    if let Some(vignette) = &config.vignette {
+        img = apply_vignette(img, vignette);
+    }
+
+    img = apply_stage(img, config.operation_order[2], config)?;
+
+    // 圆形裁剪
+    if config.circle_crop {
+        img = apply_circle_crop(img);
+    }
+
+    // 圆角遮罩
+    if let Some(rounded) = &config.rounded_corners {
+        img = apply_rounded_corners(img, rounded)?;
+    }
+
+    // 水印、滤镜等只支持 8 位的步骤会降低位深，提示调用方
+    if config.preserve_bit_depth && high_bit_depth && !is_high_bit_depth(&img) {
+        warn("'preserve_bit_depth' is set but a transform converted the image to 8-bit");
+    }
+
+    Ok(img)
+}
+
+fn apply_stage(
+    mut img: DynamicImage,
+    stage: Stage,
+    config: &ImageConfig,
+) -> Result<DynamicImage, CprError> {
+    match stage {
+        Stage::Crop => {
+            // 应用裁剪，像素裁剪优先于按宽高比裁剪
+            if let Some(crop) = &config.crop {
+                if config.crop_aspect.is_some() {
+                    warn("Both 'crop' and 'crop_aspect' are set; 'crop_aspect' is ignored");
+                }
+                img = apply_crop(img, crop)?;
+            } else if let Some(aspect) = &config.crop_aspect {
+                img = apply_crop_aspect(img, aspect, config.entropy_crop)?;
+            }
+        }
+        Stage::Resize => {
+            config.deadline.check("resizing")?;
+
+            // 应用缩放
+            if let Some(size) = &config.size {
+                // 不透明图像预乘后结果不变，跳过 16 位转换
+                let premultiply = config.premultiply_alpha && img.color().has_alpha();
+                img = if config.linear_resize || premultiply {
+                    apply_resize_rgba16(
+                        &img,
+                        size,
+                        config.preserve_bit_depth,
+                        config.entropy_crop,
+                        config.linear_resize,
+                        premultiply,
+                    )?
+                } else {
+                    apply_resize(&img, size, config.preserve_bit_depth, config.entropy_crop)?
+                };
+            }
+        }
+        Stage::Watermark => {
+            config.deadline.check("watermarking")?;
+
+            // 应用水印
+            for watermark in &config.watermarks {
+                img = apply_watermark(img, watermark)?;
+            }
+
+            // 应用文字水印
+            if let Some(text) = &config.text {
+                img = apply_text(img, text)?;
+            }
+        }
+    }
+    Ok(img)
+}
+
+fn process_image(input_data: &[u8], configs: &JsValue) -> Result<ProcessedImage, CprError> {
+    // 解析配置
+    process_with_config(input_data, ImageConfig::from_js_value(configs)?, Vec::new())
+}
+
+fn process_with_config(
+    input_data: &[u8],
+    mut config: ImageConfig,
+    scratch: Vec<u8>,
+) -> Result<ProcessedImage, CprError> {
+    let (format, output_format) = prepare_input(input_data, &mut config)?;
+
+    // GIF 动图逐帧处理并输出动图，指定 frame_index 时仍只处理单帧
+    if config.animated && config.frame_index.is_none() && format == ImageFormat::Gif {
+        return process_animation(input_data, &config, output_format);
+    }
+
+    if passes_through(input_data, format, output_format, &config) {
+        let (width, height) = image::ImageReader::with_format(Cursor::new(input_data), format)
+            .into_dimensions()
+            .map_err(|e| CprError::decode("Failed to decode input image", e))?;
+        return Ok(ProcessedImage {
+            data: input_data.to_vec(),
+            width,
+            height,
+            format,
+            quality: None,
+        });
+    }
+
+    let img = decode_input(input_data, format, &config)?;
+    config.deadline.check("transforming")?;
+    let img = transform_image(img, input_data, &config)?;
+
+    // 编码结果记录最终尺寸，按尺寸满足 target_size_bytes 时可能小于变换后的尺寸
+    config.deadline.check("encoding")?;
+    encode_to_target_size(img, output_format, &config.encode, scratch)
+}
+
+// 解码前的准备：确定输入与输出格式、检查输入大小，并补全依赖输入内容的配置
+fn prepare_input(
+    input_data: &[u8],
+    config: &mut ImageConfig,
+) -> Result<(ImageFormat, ImageFormat), CprError> {
+    let format = resolve_input_format(input_data, config.format.as_deref())?;
+
+    // 确定输出格式
+    let output_format = resolve_output_format(config, format)?;
+
+    // 无损格式没有质量参数，提示调用方 quality 不生效
+    if config.encode.quality.is_some()
+        && matches!(
+            output_format,
+            ImageFormat::Png
+                | ImageFormat::Bmp
+                | ImageFormat::Tiff
+                | ImageFormat::Gif
+                | ImageFormat::Ico
+                | ImageFormat::Qoi
+        )
+    {
+        warn("'quality' only applies to lossy formats and is ignored for this output format");
+    }
+    if config.encode.target_size_bytes.is_some()
+        && output_format != ImageFormat::Jpeg
+        && !config.encode.target_size_downscale
+    {
+        warn("'target_size_bytes' only applies to JPEG output unless 'target_size_downscale' is enabled, and is ignored");
+    }
+
+    // 解码前检查输入大小
+    check_input_limits(
+        input_data,
+        format,
+        config.max_pixels,
+        config.max_input_bytes,
+    )?;
+
+    // 保留元数据时读取输入的 ICC 配置文件
+    if !config.encode.strip_metadata {
+        config.encode.icc_profile = read_icc_profile(input_data, format);
+    }
+    config.encode.grayscale = config.grayscale;
+    resolve_crop_orientation(config, input_data);
+    Ok((format, output_format))
+}
+
+fn decode_input(
+    input_data: &[u8],
+    format: ImageFormat,
+    config: &ImageConfig,
+) -> Result<DynamicImage, CprError> {
+    // 加载图像，GIF 动图可指定帧序号；需要缩小的 JPEG 优先在解码阶段按 2 的幂次缩小
+    let scaled = match (format, config.frame_index) {
+        (ImageFormat::Jpeg, None) => decode_jpeg_scaled(input_data, config),
+        _ => None,
+    };
+    match (scaled, config.frame_index, config.ico_index) {
+        (Some(img), _, _) => Ok(img),
+        (None, _, Some(index)) if format == ImageFormat::Ico => decode_ico_entry(input_data, index),
+        (None, Some(index), _) => decode_frame(input_data, format, index),
+        (None, None, _) => decode_image(input_data, format),
+    }
+}
+
+fn process_animation(
+    input_data: &[u8],
+    config: &ImageConfig,
+    output_format: ImageFormat,
+) -> Result<ProcessedImage, CprError> {
+    if !matches!(output_format, ImageFormat::Gif | ImageFormat::WebP) {
+        return Err(CprError::UnsupportedOutputFormat(
+            "Animated output format must be 'gif' or 'webp'".into(),
+        ));
+    }
+
+    // 对每一帧执行同样的变换，并保留原帧延时
+    let decoder = GifDecoder::new(Cursor::new(input_data))
+        .map_err(|e| CprError::decode("Failed to decode input image", e))?;
+    let mut frames = Vec::new();
+    for frame in decoder.into_frames() {
+        let frame = frame.map_err(|e| CprError::decode("Failed to decode GIF frame", e))?;
+        let delay = frame.delay();
+        let img = DynamicImage::ImageRgba8(frame.into_buffer());
+        frames.push((
+            transform_image(img, input_data, config)?.into_rgba8(),
+            delay,
+        ));
+    }
+    let (width, height) = frames
+        .first()
+        .map(|(frame, _)| frame.dimensions())
+        .ok_or(CprError::DecodeFailed("GIF has no frames".into()))?;
+
+    config.deadline.check("encoding")?;
+    let data = encode_animation(frames, output_format, &config.encode)?;
+    Ok(ProcessedImage {
+        data,
+        width,
+        height,
+        format: output_format,
+        quality: None,
+    })
+}
+
+fn encode_animation(
+    frames: Vec<(RgbaImage, Delay)>,
+    format: ImageFormat,
+    options: &EncodeOptions,
+) -> Result<Vec<u8>, CprError> {
+    match format {
+        ImageFormat::Gif => {
+            let mut buf = Vec::new();
+            {
+                let mut encoder = GifEncoder::new(&mut buf);
+                encoder.set_repeat(Repeat::Infinite)?;
+                encoder.encode_frames(frames.into_iter().map(|(frame, delay)| {
+                    let frame = if options.dither {
+                        dither_gif_frame(frame)
+                    } else {
+                        frame
+                    };
+                    Frame::from_parts(frame, 0, 0, delay)
+                }))?;
+            }
+            Ok(buf)
+        }
+        #[cfg(feature = "libwebp")]
+        ImageFormat::WebP => {
+            // 与单帧一致地选择有损或无损编码
+            let mut webp_config = webp::WebPConfig::new()
+                .map_err(|_| CprError::EncodeFailed("Failed to initialize WebP encoder".into()))?;
+            match options.webp_lossy_quality() {
+                Some(quality) => {
+                    webp_config.lossless = 0;
+                    webp_config.quality = quality;
+                }
+                None => webp_config.lossless = 1,
+            }
+
+            let (width, height) = frames
+                .first()
+                .map(|(frame, _)| frame.dimensions())
+                .unwrap_or((0, 0));
+            let mut encoder = webp::AnimEncoder::new(width, height, &webp_config);
+            // WebP 动图使用每帧的起始时间戳（毫秒）
+            let mut timestamp = 0;
+            for (frame, delay) in &frames {
+                encoder.add_frame(webp::AnimFrame::from_rgba(
+                    frame.as_raw(),
+                    width,
+                    height,
+                    timestamp,
+                ));
+                let (numer, denom) = delay.numer_denom_ms();
+                timestamp += (numer / denom.max(1)) as i32;
+            }
+            let encoded = encoder.try_encode().map_err(|e| {
+                CprError::EncodeFailed(format!("Failed to encode animated WebP: {:?}", e))
+            })?;
+            Ok(encoded.to_vec())
+        }
+        // image 自带的 WebP 编码器不支持动图
+        #[cfg(not(feature = "libwebp"))]
+        ImageFormat::WebP => Err(CprError::UnsupportedOutputFormat(
+            "Animated WebP output requires building with the 'libwebp' feature".into(),
+        )),
+        _ => Err(CprError::UnsupportedOutputFormat(
+            "Animated output format must be 'gif' or 'webp'".into(),
+        )),
+    }
+}
+
+#[wasm_bindgen]
+pub fn image_cpr(input_data: &[u8], configs: &JsValue) -> Result<Vec<u8>, CprError> {
+    Ok(process_image(input_data, configs)?.data)
+}
+
+// 解码一次后按 sizes 中的每个宽度分别变换并编码，用于生成 HTML srcset
+#[wasm_bindgen]
+pub fn image_cpr_srcset(input_data: &[u8], configs: &JsValue) -> Result<JsValue, CprError> {
+    let config = ImageConfig::from_js_value(configs)?;
+    let widths = parse_srcset_widths(configs)?;
+    let once = parse_srcset_crop_watermark(configs)?;
+
+    let results = js_sys::Array::new();
+    process_srcset(input_data, config, &widths, once, |processed| {
+        results.push(&build_js_object(&[
+            ("width", JsValue::from(processed.width)),
+            (
+                "data",
+                JsValue::from(Uint8Array::from(processed.data.as_slice())),
+            ),
+        ])?);
+        Ok(())
+    })?;
+    Ok(results.into())
+}
+
+// once 为 true 时裁剪与水印在缩放前对原图只执行一次，各宽度复用该中间结果；
+// 每个结果交给 emit 后，其缓冲作为下一个宽度的输出缓冲
+fn process_srcset(
+    input_data: &[u8],
+    mut config: ImageConfig,
+    widths: &[u32],
+    once: bool,
+    mut emit: impl FnMut(&ProcessedImage) -> Result<(), CprError>,
+) -> Result<(), CprError> {
+    // 各宽度沿用 size 中的滤波器与适配模式，高度按宽高比计算
+    let base_size = config.size.take();
+    if base_size
+        .as_ref()
+        .is_some_and(|size| size.height.is_some() || size.scale.is_some())
+    {
+        return Err(CprError::InvalidConfig(
+            "'size.height' and 'size.scale' are not supported by image_cpr_srcset; each output height follows its width".into(),
+        ));
+    }
+    let size_for = |width| SizeConfig {
+        width: Some(width),
+        ..base_size.clone().unwrap_or(SizeConfig {
+            width: None,
+            height: None,
+            scale: None,
+            filter: None,
+            downscale_filter: None,
+            upscale_filter: None,
+            fit: None,
+            allow_upscale: true,
+        })
+    };
+
+    if once {
+        // 水印移到缩放之前，与 operation_order 为 ["crop", "watermark", "resize"] 时相同
+        if config.operation_order != DEFAULT_OPERATION_ORDER {
+            return Err(CprError::InvalidConfig(
+                "'crop_watermark' of 'once' cannot be combined with 'operation_order'".into(),
+            ));
+        }
+        config.operation_order = vec![Stage::Crop, Stage::Watermark, Stage::Resize];
+    }
+
+    let (format, output_format) = prepare_input(input_data, &mut config)?;
+    // JPEG 按最大宽度缩小解码，其余宽度在此基础上继续缩小
+    config.size = widths.iter().max().map(|&width| size_for(width));
+    let img = decode_input(input_data, format, &config)?;
+    let high_bit_depth = is_high_bit_depth(&img);
+    let img = match once {
+        true => {
+            config.deadline.check("transforming")?;
+            transform_head(img, input_data, &config)?
+        }
+        false => img,
+    };
+
+    let mut scratch = Vec::new();
+    for &width in widths {
+        config.size = Some(size_for(width));
+        config.deadline.check("transforming")?;
+        let transformed = match once {
+            true => transform_tail(img.clone(), &config, high_bit_depth)?,
+            false => transform_image(img.clone(), input_data, &config)?,
+        };
+        config.deadline.check("encoding")?;
+        let processed = encode_to_target_size(transformed, output_format, &config.encode, scratch)?;
+        emit(&processed)?;
+        scratch = processed.data;
+    }
+    Ok(())
+}
+
+fn parse_srcset_crop_watermark(configs: &JsValue) -> Result<bool, CprError> {
+    match Reflect::get(configs, &JsValue::from_str("crop_watermark"))
+        .map_err(|_| CprError::InvalidConfig("Failed to get 'crop_watermark' from configs".into()))?
+        .as_string()
+        .as_deref()
+    {
+        None | Some("per_size") => Ok(false),
+        Some("once") => Ok(true),
+        Some(_) => Err(CprError::InvalidConfig(
+            "'crop_watermark' must be 'per_size' or 'once'".into(),
+        )),
+    }
+}
+
+fn parse_srcset_widths(configs: &JsValue) -> Result<Vec<u32>, CprError> {
+    let invalid =
+        || CprError::InvalidConfig("'sizes' must be a non-empty array of positive widths".into());
+    let arr = Reflect::get(configs, &JsValue::from_str("sizes"))
+        .map_err(|_| CprError::InvalidConfig("Failed to get 'sizes' from configs".into()))?
+        .dyn_into::<js_sys::Array>()
+        .map_err(|_| invalid())?;
+    let mut widths = Vec::new();
+    for value in arr.iter() {
+        match value.as_f64() {
+            Some(width) if width >= 1.0 && width <= u32::MAX as f64 => widths.push(width as u32),
+            _ => return Err(invalid()),
+        }
+    }
+    if widths.is_empty() {
+        return Err(invalid());
+    }
+    Ok(widths)
+}
+
+// 循环调用时复用输入与输出缓冲，跨调用摊销内存分配；单次调用直接使用 image_cpr 即可
+#[wasm_bindgen]
+#[derive(Default)]
+pub struct ImageCprContext {
+    input: Vec<u8>,
+    output: Vec<u8>,
+}
+
+#[wasm_bindgen]
+impl ImageCprContext {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> ImageCprContext {
+        ImageCprContext::default()
+    }
+
+    // 输入复制进自身缓冲，输出复制给 JS 后保留缓冲供下次编码使用
+    pub fn process(
+        &mut self,
+        input: &Uint8Array,
+        configs: &JsValue,
+    ) -> Result<Uint8Array, CprError> {
+        self.input.resize(input.length() as usize, 0);
+        input.copy_to(&mut self.input);
+        let config = ImageConfig::from_js_value(configs)?;
+        let processed = process_with_config(&self.input, config, std::mem::take(&mut self.output))?;
+        let data = Uint8Array::from(processed.data.as_slice());
+        self.output = processed.data;
+        Ok(data)
+    }
+}
+
+// wasm 只能读取自身线性内存，JS 侧的数据至少要复制一次；这里只复制 [offset, offset + length) 这一段，
+// 不复制整个缓冲区，且无需 JS 先创建 subarray
+#[wasm_bindgen]
+pub fn image_cpr_range(
+    buffer: &Uint8Array,
+    offset: u32,
+    length: u32,
+    configs: &JsValue,
+) -> Result<Vec<u8>, CprError> {
+    let end = offset
+        .checked_add(length)
+        .filter(|&end| end <= buffer.length())
+        .ok_or_else(|| {
+            CprError::InvalidConfig(format!(
+                "Range {}..{} exceeds the buffer length {}",
+                offset,
+                offset as u64 + length as u64,
+                buffer.length()
+            ))
+        })?;
+    image_cpr(&buffer.subarray(offset, end).to_vec(), configs)
+}
+
+// 将 overlay 作为最后一个水印叠加到 base 上，其余配置与 image_cpr 相同
+#[wasm_bindgen]
+pub fn composite(base: &[u8], overlay: &[u8], configs: &JsValue) -> Result<Vec<u8>, CprError> {
+    let configs_obj = configs
+        .dyn_ref::<js_sys::Object>()
+        .ok_or(CprError::InvalidConfig(
+            "'configs' must be an object".into(),
+        ))?;
+    let mut config = ImageConfig::from_js_value(configs)?;
+    let mut placement = WatermarkConfig::with_content(configs_obj, overlay.to_vec())?;
+
+    // 未指定尺寸时按 overlay 原始尺寸叠加
+    if placement.position[2] == 0 && placement.position[3] == 0 {
+        let format = resolve_input_format(overlay, None)?;
+        let (width, height) = image::ImageReader::with_format(Cursor::new(overlay), format)
+            .into_dimensions()
+            .map_err(|e| CprError::decode("Failed to load overlay", e))?;
+        placement.position[2] = width as i64;
+        placement.position[3] = height as i64;
+    }
+    config.watermarks.push(placement);
+    Ok(process_with_config(base, config, Vec::new())?.data)
+}
+
+#[wasm_bindgen]
+pub fn image_cpr_base64(input_base64: &str, configs: &JsValue) -> Result<Vec<u8>, CprError> {
+    image_cpr(&decode_base64_input(input_base64)?, configs)
+}
+
+fn decode_base64_input(input_base64: &str) -> Result<Vec<u8>, CprError> {
+    // 兼容 data URL 形式，去掉 "data:...;base64," 前缀
+    let encoded = match input_base64.trim_start().strip_prefix("data:") {
+        Some(rest) => {
+            rest.split_once(";base64,")
+                .ok_or(CprError::InvalidInputFormat(
+                    "Data URL input must be base64-encoded".into(),
+                ))?
+                .1
         }
-        ImageFormat::Png => {
-            let encoder = PngEncoder::new_with_quality(
-                &mut buf,
-                CompressionType::Best,
-                image::codecs::png::FilterType::Paeth,
-            );
-            img.write_with_encoder(encoder)?;
+        None => input_base64,
+    };
+    // 忽略换行等空白字符，常见于按行折断的 base64
+    let encoded: Vec<u8> = encoded
+        .bytes()
+        .filter(|b| !b.is_ascii_whitespace())
+        .collect();
+    STANDARD
+        .decode(encoded)
+        .map_err(|e| CprError::InvalidInputFormat(format!("Invalid base64 input: {}", e)))
+}
+
+#[wasm_bindgen]
+pub fn image_cpr_with_meta(input_data: &[u8], configs: &JsValue) -> Result<JsValue, CprError> {
+    let processed = process_image(input_data, configs)?;
+
+    let data = Uint8Array::from(processed.data.as_slice());
+    let format = processed.format.extensions_str()[0];
+    build_js_object(&[
+        ("data", JsValue::from(data)),
+        ("width", JsValue::from(processed.width)),
+        ("height", JsValue::from(processed.height)),
+        ("format", JsValue::from_str(format)),
+        (
+            "quality",
+            processed.quality.map_or(JsValue::UNDEFINED, JsValue::from),
+        ),
+    ])
+}
+
+#[wasm_bindgen]
+pub fn base64_data_url(input_data: &[u8], configs: &JsValue) -> Result<String, CprError> {
+    let processed = process_image(input_data, configs)?;
+
+    // MIME 类型取自实际输出格式，结果可直接用作 <img src>
+    Ok(format!(
+        "data:{};base64,{}",
+        processed.format.to_mime_type(),
+        STANDARD.encode(&processed.data)
+    ))
+}
+
+#[wasm_bindgen]
+pub fn validate_only(input_data: &[u8], configs: &JsValue) -> Result<JsValue, CprError> {
+    let errors = js_sys::Array::new();
+    for err in validate_config(input_data, configs) {
+        errors.push(&err.into());
+    }
+    build_js_object(&[
+        ("valid", JsValue::from_bool(errors.length() == 0)),
+        ("errors", errors.into()),
+    ])
+}
+
+fn validate_config(input_data: &[u8], configs: &JsValue) -> Vec<CprError> {
+    // 配置无法解析时无法继续校验
+    let mut config = match ImageConfig::from_js_value(configs) {
+        Ok(config) => config,
+        Err(err) => return vec![err],
+    };
+    resolve_crop_orientation(&mut config, input_data);
+
+    let mut errors = Vec::new();
+    let format = match resolve_input_format(input_data, config.format.as_deref()) {
+        Ok(format) => format,
+        Err(err) => return vec![err],
+    };
+    if let Err(err) = resolve_output_format(&config, format) {
+        errors.push(err);
+    }
+    // 只读取文件头获取尺寸，不解码像素
+    let (width, height) = match check_input_limits(
+        input_data,
+        format,
+        config.max_pixels,
+        config.max_input_bytes,
+    ) {
+        Ok(dimensions) => dimensions,
+        Err(err) => {
+            errors.push(err);
+            return errors;
         }
-        ImageFormat::WebP => {
-            let encoder = WebPEncoder::new_lossless(&mut buf);
-            img.write_with_encoder(encoder)?;
+    };
+
+    let mut watermark_sizes = Vec::new();
+    for watermark in &config.watermarks {
+        let header = image::ImageReader::new(Cursor::new(&watermark.content))
+            .with_guessed_format()
+            .map_err(image::ImageError::IoError)
+            .and_then(|reader| reader.into_dimensions());
+        match header {
+            Ok(source_size) => watermark_sizes.push(Some(source_size)),
+            Err(e) => {
+                errors.push(CprError::decode("Failed to load watermark", e));
+                watermark_sizes.push(None);
+            }
         }
-        _ => return Err(JsError::new("Unsupported output format")),
     }
-    Ok(buf.into_inner())
+    let font = match config.text.as_ref().map(load_text_font).transpose() {
+        Ok(font) => font,
+        Err(err) => {
+            errors.push(err);
+            None
+        }
+    };
+    let overlays = OverlaySizes {
+        watermark_sizes,
+        font,
+    };
+
+    // 按流水线顺序推算各阶段后的尺寸，出错后无法得知后续尺寸，停止推算
+    if let Err(err) = validate_geometry(&config, input_data, width, height, &overlays, &mut errors)
+    {
+        errors.push(err);
+    }
+    errors
+}
+
+// 校验时测量水印与文字所需的信息，无法读取的项为 None 并跳过位置检查
+struct OverlaySizes {
+    watermark_sizes: Vec<Option<(u32, u32)>>,
+    font: Option<FontArc>,
+}
+
+fn validate_geometry(
+    config: &ImageConfig,
+    input_data: &[u8],
+    mut width: u32,
+    mut height: u32,
+    overlays: &OverlaySizes,
+    errors: &mut Vec<CprError>,
+) -> Result<(), CprError> {
+    if config.auto_orient && read_exif_orientation(input_data).is_some_and(swaps_axes) {
+        (width, height) = (height, width);
+    }
+    // 去边后的尺寸须解码才能得知，无法继续推算
+    if config.trim.is_some() {
+        return Ok(());
+    }
+
+    let [first, second, third] = [0, 1, 2].map(|i| config.operation_order[i]);
+    (width, height) = stage_dimensions(first, config, width, height, overlays, errors)?;
+    match config.rotate {
+        Some(90 | 270) => (width, height) = (height, width),
+        None | Some(180) => {}
+        Some(_) => {
+            return Err(CprError::InvalidConfig(
+                "'rotate' must be one of 90, 180 or 270".into(),
+            ))
+        }
+    }
+    (width, height) = stage_dimensions(second, config, width, height, overlays, errors)?;
+    if let Some(thumbnail) = &config.thumbnail {
+        (width, height) = thumbnail_size(width, height, thumbnail);
+    }
+    if let Some(pad) = &config.pad {
+        (width, height) = (pad.width, pad.height);
+    }
+    if let Some(pixelate) = &config.pixelate {
+        if let Err(err) = pixelate_region(pixelate, width, height) {
+            errors.push(err);
+        }
+    }
+    (width, height) = stage_dimensions(third, config, width, height, overlays, errors)?;
+    if config.circle_crop {
+        let side = width.min(height);
+        (width, height) = (side, side);
+    }
+    if let Some(rounded) = &config.rounded_corners {
+        if let Err(err) = corner_radii(rounded, width, height) {
+            errors.push(err);
+        }
+    }
+    Ok(())
+}
+
+fn stage_dimensions(
+    stage: Stage,
+    config: &ImageConfig,
+    width: u32,
+    height: u32,
+    overlays: &OverlaySizes,
+    errors: &mut Vec<CprError>,
+) -> Result<(u32, u32), CprError> {
+    match stage {
+        Stage::Crop => {
+            if let Some(crop) = &config.crop {
+                let [_, _, crop_width, crop_height] = crop_rect(crop, width, height)?;
+                return Ok((crop_width, crop_height));
+            }
+            if let Some(aspect) = &config.crop_aspect {
+                return Ok(aspect_crop_size(aspect, width, height));
+            }
+        }
+        Stage::Resize => {
+            if let Some(size) = &config.size {
+                return resized_dimensions(width, height, size);
+            }
+        }
+        Stage::Watermark => {
+            // 各水印互不影响尺寸，逐个收集错误
+            for (watermark, source_size) in config.watermarks.iter().zip(&overlays.watermark_sizes)
+            {
+                if let Some(source_size) = *source_size {
+                    if let Err(err) = place_watermark(watermark, source_size, width, height) {
+                        errors.push(err);
+                    }
+                }
+            }
+            if let (Some(text), Some(font)) = (&config.text, &overlays.font) {
+                if let Err(err) = place_text(text, font, width, height) {
+                    errors.push(err);
+                }
+            }
+        }
+    }
+    Ok((width, height))
 }
 
 #[wasm_bindgen]
-pub fn image_cpr(input_data: &[u8], configs: &JsValue) -> Result<Vec<u8>, JsError> {
-    // 解析配置
-    let config = ImageConfig::from_js_value(configs)?;
+pub fn image_cpr_batch(inputs: Vec<Uint8Array>, configs: &JsValue) -> Result<JsValue, CprError> {
+    let results = js_sys::Array::new();
+    for input in inputs {
+        // 单张失败只记录错误，不中断整批处理
+        let result = match process_image(&input.to_vec(), configs) {
+            Ok(processed) => build_js_object(&[
+                ("success", JsValue::TRUE),
+                ("data", Uint8Array::from(processed.data.as_slice()).into()),
+            ])?,
+            Err(err) => build_js_object(&[("success", JsValue::FALSE), ("error", err.into())])?,
+        };
+        results.push(&result);
+    }
+    Ok(results.into())
+}
+
+fn build_js_object(fields: &[(&str, JsValue)]) -> Result<JsValue, CprError> {
+    let object = js_sys::Object::new();
+    for (key, value) in fields {
+        Reflect::set(&object, &JsValue::from_str(key), value)
+            .map_err(|_| CprError::Internal(format!("Failed to set '{}' on result", key)))?;
+    }
+    Ok(object.into())
+}
 
-    let format =
-        ImageFormat::from_extension(&config.format).ok_or(JsError::new("Invalid input format"))?;
+#[wasm_bindgen]
+pub fn image_dimensions(input_data: &[u8], format: Option<String>) -> Result<JsValue, CprError> {
+    // 只读取文件头，不解码像素
+    let format = resolve_input_format(input_data, format.as_deref())?;
+    let (width, height) = image::ImageReader::with_format(Cursor::new(input_data), format)
+        .into_dimensions()
+        .map_err(|e| CprError::decode("Failed to read image dimensions", e))?;
+    build_js_object(&[
+        ("width", JsValue::from(width)),
+        ("height", JsValue::from(height)),
+    ])
+}
+
+#[wasm_bindgen]
+pub fn supported_formats() -> Result<JsValue, CprError> {
+    // 输入格式取决于 image 启用的解码特性，输出格式取决于 encode_image 的编码分支
+    let extensions = |formats: &mut dyn Iterator<Item = ImageFormat>| {
+        formats
+            .filter_map(|format| format.extensions_str().first())
+            .map(|extension| JsValue::from_str(extension))
+            .collect::<js_sys::Array>()
+    };
+    build_js_object(&[
+        (
+            "input",
+            extensions(&mut ImageFormat::all().filter(|format| format.reading_enabled())).into(),
+        ),
+        ("output", extensions(&mut OUTPUT_FORMATS.into_iter()).into()),
+    ])
+}
+
+#[wasm_bindgen]
+pub fn dominant_color(input_data: &[u8], format: &str) -> Result<String, CprError> {
+    let format = resolve_input_format(input_data, Some(format))?;
+    check_input_limits(input_data, format, DEFAULT_MAX_PIXELS, None)?;
+    // 先缩小再统计，避免逐像素处理大图
+    let img = apply_thumbnail(
+        decode_image(input_data, format)?,
+        &ThumbnailConfig { max_edge: 64 },
+    );
+    let img_rgba = img.into_rgba8();
 
-    // 加载图像
-    let mut img = image::load_from_memory_with_format(input_data, format)?;
+    // 忽略大部分透明的像素，全透明时退回统计全部像素
+    let mut pixels: Vec<[u8; 3]> = img_rgba
+        .pixels()
+        .filter(|p| p[3] >= 128)
+        .map(|p| [p[0], p[1], p[2]])
+        .collect();
+    if pixels.is_empty() {
+        pixels = img_rgba.pixels().map(|p| [p[0], p[1], p[2]]).collect();
+    }
 
-    // 应用裁剪
-    if let Some(crop) = config.crop {
-        img = apply_crop(img, &crop)?;
+    let dominant = median_cut(pixels, DOMINANT_COLOR_BOXES)
+        .into_iter()
+        .max_by_key(|pixels| pixels.len())
+        .unwrap_or_default();
+    if dominant.is_empty() {
+        return Err(CprError::DecodeFailed("Image has no pixels".into()));
+    }
+    let mut sum = [0u64; 3];
+    for p in &dominant {
+        for c in 0..3 {
+            sum[c] += p[c] as u64;
+        }
     }
+    let count = dominant.len() as u64;
+    Ok(format!(
+        "#{:02x}{:02x}{:02x}",
+        sum[0] / count,
+        sum[1] / count,
+        sum[2] / count
+    ))
+}
 
-    // 应用缩放
-    if let Some(size) = config.size {
-        img = apply_resize(&img, &size);
+#[wasm_bindgen]
+pub fn image_stats(input_data: &[u8], format: &str) -> Result<JsValue, CprError> {
+    let format = resolve_input_format(input_data, Some(format))?;
+    check_input_limits(input_data, format, DEFAULT_MAX_PIXELS, None)?;
+    let img_rgba = decode_image(input_data, format)?.into_rgba8();
+    if img_rgba.is_empty() {
+        return Err(CprError::DecodeFailed("Image has no pixels".into()));
     }
 
-    // 应用水印
-    if let Some(watermark) = config.watermark {
-        img = apply_watermark(&img, &watermark)?;
+    // 单次遍历同时统计各通道最值、累加和与亮度直方图
+    let mut min = [u8::MAX; 4];
+    let mut max = [u8::MIN; 4];
+    let mut sum = [0u64; 4];
+    let mut histogram = [0u32; 256];
+    for p in img_rgba.pixels() {
+        for c in 0..4 {
+            min[c] = min[c].min(p[c]);
+            max[c] = max[c].max(p[c]);
+            sum[c] += p[c] as u64;
+        }
+        // 与 grayscale 相同的 Rec. 709 亮度系数
+        let luma = (2126 * p[0] as u32 + 7152 * p[1] as u32 + 722 * p[2] as u32) / 10000;
+        histogram[luma as usize] += 1;
     }
 
-    // 确定输出格式
-    let output_format_str = config.output_format.unwrap_or(config.format);
-    let output_format = ImageFormat::from_extension(&output_format_str)
-        .ok_or(JsError::new("Invalid output format"))?;
+    let count = (img_rgba.width() as u64 * img_rgba.height() as u64) as f64;
+    let mut channels = Vec::new();
+    for (c, name) in ["r", "g", "b", "a"].into_iter().enumerate() {
+        let stats = build_js_object(&[
+            ("min", JsValue::from(min[c])),
+            ("max", JsValue::from(max[c])),
+            ("mean", JsValue::from(sum[c] as f64 / count)),
+        ])?;
+        channels.push((name, stats));
+    }
+    let channels = build_js_object(&channels)?;
+
+    build_js_object(&[
+        ("width", JsValue::from(img_rgba.width())),
+        ("height", JsValue::from(img_rgba.height())),
+        ("channels", channels),
+        (
+            "histogram",
+            js_sys::Uint32Array::from(histogram.as_slice()).into(),
+        ),
+    ])
+}
+
+#[wasm_bindgen]
+pub fn blurhash(
+    input_data: &[u8],
+    format: &str,
+    x_comp: u32,
+    y_comp: u32,
+) -> Result<String, CprError> {
+    if !(1..=9).contains(&x_comp) || !(1..=9).contains(&y_comp) {
+        return Err(CprError::InvalidConfig(
+            "'x_comp' and 'y_comp' must be between 1 and 9".into(),
+        ));
+    }
+    let format = resolve_input_format(input_data, Some(format))?;
+    check_input_limits(input_data, format, DEFAULT_MAX_PIXELS, None)?;
+    // BlurHash 只保留低频信息，缩小到约 32px 即可大幅减少计算量
+    let img = apply_thumbnail(
+        decode_image(input_data, format)?,
+        &ThumbnailConfig { max_edge: 32 },
+    );
+    let img_rgba = img.into_rgba8();
+    blurhash::encode(
+        x_comp,
+        y_comp,
+        img_rgba.width(),
+        img_rgba.height(),
+        img_rgba.as_raw(),
+    )
+    .map_err(|e| CprError::EncodeFailed(format!("Failed to compute BlurHash: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 与 EncodeOptions::from_js_value 的缺省值一致
+    fn encode_options() -> EncodeOptions {
+        EncodeOptions {
+            quality: None,
+            target_size_bytes: None,
+            target_size_downscale: false,
+            progressive: false,
+            jpeg_subsampling: None,
+            flatten_background: Rgba([255, 255, 255, 255]),
+            on_alpha_to_jpeg: AlphaToJpeg::Flatten,
+            dpi: None,
+            lossless: None,
+            png_compression: CompressionType::Best,
+            png_filter: PngFilterType::Paeth,
+            png_palette: false,
+            max_colors: 256,
+            dither: false,
+            speed: 4,
+            tiff_compression: TiffCompression::None,
+            ico_sizes: None,
+            ico_auto_resize: true,
+            strip_metadata: true,
+            icc_profile: None,
+            grayscale: false,
+        }
+    }
+
+    // 与 ImageConfig::from_js_value 在空配置下的结果一致
+    fn default_config() -> ImageConfig {
+        ImageConfig {
+            format: None,
+            max_pixels: DEFAULT_MAX_PIXELS,
+            max_input_bytes: None,
+            preserve_bit_depth: false,
+            deadline: Deadline::new(None),
+            frame_index: None,
+            ico_index: None,
+            animated: false,
+            auto_orient: false,
+            trim: None,
+            denoise: None,
+            chroma_key: None,
+            crop: None,
+            crop_aspect: None,
+            entropy_crop: false,
+            rotate: None,
+            flip_horizontal: false,
+            flip_vertical: false,
+            size: None,
+            linear_resize: false,
+            premultiply_alpha: true,
+            thumbnail: None,
+            pad: None,
+            grayscale: false,
+            auto_levels: None,
+            brightness: None,
+            contrast: None,
+            blur: None,
+            sharpen: None,
+            sepia: false,
+            invert: false,
+            gamma: None,
+            saturation: None,
+            edges: false,
+            threshold: None,
+            pixelate: None,
+            overlay: None,
+            vignette: None,
+            watermarks: Vec::new(),
+            text: None,
+            rounded_corners: None,
+            circle_crop: false,
+            operation_order: DEFAULT_OPERATION_ORDER.to_vec(),
+            output_format: None,
+            force_reencode: false,
+            encode: encode_options(),
+        }
+    }
+
+    fn size_config(width: Option<u32>, height: Option<u32>, scale: Option<f64>) -> SizeConfig {
+        SizeConfig {
+            width,
+            height,
+            scale,
+            filter: None,
+            downscale_filter: None,
+            upscale_filter: None,
+            fit: None,
+            allow_upscale: true,
+        }
+    }
+
+    fn gradient(width: u32, height: u32) -> RgbImage {
+        RgbImage::from_fn(width, height, |x, y| {
+            Rgb([(x * 255 / width) as u8, (y * 255 / height) as u8, 128])
+        })
+    }
+
+    fn encode(img: DynamicImage, format: ImageFormat, options: &EncodeOptions) -> Vec<u8> {
+        encode_image(img, format, options, Vec::new()).unwrap()
+    }
+
+    #[test]
+    fn gif_output_starts_with_magic() {
+        let img = DynamicImage::ImageRgb8(gradient(16, 8));
+        let data = encode(img, ImageFormat::Gif, &encode_options());
+        assert!(data.starts_with(b"GIF89a") || data.starts_with(b"GIF87a"));
+    }
+
+    #[test]
+    fn bmp_round_trip_keeps_dimensions() {
+        let gray =
+            DynamicImage::ImageLuma8(GrayImage::from_fn(7, 5, |x, y| Luma([(x * 30 + y) as u8])));
+        let rgb = DynamicImage::ImageRgb8(gradient(9, 4));
+        for img in [gray, rgb] {
+            let data = encode(img.clone(), ImageFormat::Bmp, &encode_options());
+            let decoded = image::load_from_memory(&data).unwrap();
+            assert_eq!(
+                (decoded.width(), decoded.height()),
+                (img.width(), img.height())
+            );
+            assert_eq!(decoded.to_rgb8(), img.to_rgb8());
+        }
+    }
+
+    #[test]
+    fn progressive_jpeg_decodes() {
+        let img = DynamicImage::ImageRgb8(gradient(32, 24));
+        let options = EncodeOptions {
+            progressive: true,
+            ..encode_options()
+        };
+        let data = encode(img, ImageFormat::Jpeg, &options);
+        // SOF2 标记表示渐进式 DCT
+        assert!(data.windows(2).any(|marker| marker == [0xFF, 0xC2]));
+        let decoded = image::load_from_memory_with_format(&data, ImageFormat::Jpeg).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (32, 24));
+    }
+
+    // 在 JPEG 的 SOI 之后插入只含 Orientation 字段的 EXIF APP1 段
+    fn jpeg_with_orientation(orientation: u16) -> Vec<u8> {
+        let mut tiff = b"II*\0\x08\0\0\0\x01\0\x12\x01\x03\0\x01\0\0\0".to_vec();
+        tiff.extend_from_slice(&orientation.to_le_bytes());
+        tiff.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+        let payload = [b"Exif\0\0".as_slice(), &tiff].concat();
+        let jpeg = encode(
+            DynamicImage::ImageRgb8(gradient(4, 4)),
+            ImageFormat::Jpeg,
+            &encode_options(),
+        );
+        let mut data = vec![0xFF, 0xD8, 0xFF, 0xE1];
+        data.extend_from_slice(&(payload.len() as u16 + 2).to_be_bytes());
+        data.extend_from_slice(&payload);
+        data.extend_from_slice(&jpeg[2..]);
+        data
+    }
+
+    #[test]
+    fn auto_orient_applies_exif_orientation() {
+        // 3×2 的灰度图，像素值为 x + 10y
+        let img =
+            DynamicImage::ImageLuma8(GrayImage::from_fn(3, 2, |x, y| Luma([(x + 10 * y) as u8])));
+        let cases: [(u16, (u32, u32), [u8; 2]); 4] = [
+            (1, (3, 2), [0, 12]),
+            (3, (3, 2), [12, 0]),
+            (6, (2, 3), [10, 2]),
+            (8, (2, 3), [2, 10]),
+        ];
+        for (orientation, dimensions, [first, last]) in cases {
+            let input = jpeg_with_orientation(orientation);
+            assert_eq!(
+                read_exif_orientation(&input).map(|o| o.to_exif()),
+                Some(orientation as u8)
+            );
+            let oriented = apply_auto_orient(img.clone(), &input).into_luma8();
+            assert_eq!(
+                oriented.dimensions(),
+                dimensions,
+                "orientation {}",
+                orientation
+            );
+            let (width, height) = dimensions;
+            assert_eq!(
+                oriented.get_pixel(0, 0)[0],
+                first,
+                "orientation {}",
+                orientation
+            );
+            assert_eq!(
+                oriented.get_pixel(width - 1, height - 1)[0],
+                last,
+                "orientation {}",
+                orientation
+            );
+        }
+    }
+
+    #[test]
+    fn crop_space_maps_rotated_orientations() {
+        // 与上例相同的 3×2 图像；存储坐标 [1, 0, 2, 1] 与显示坐标 [1, 0, 1, 2] 都得到 1×2 的区域
+        let img =
+            DynamicImage::ImageLuma8(GrayImage::from_fn(3, 2, |x, y| Luma([(x + 10 * y) as u8])));
+        let cases = [
+            (6, CropSpace::Stored, [1, 0, 2, 1], [1, 2]),
+            (6, CropSpace::Display, [1, 0, 1, 2], [0, 1]),
+            (8, CropSpace::Stored, [1, 0, 2, 1], [2, 1]),
+            (8, CropSpace::Display, [1, 0, 1, 2], [12, 11]),
+        ];
+        for (orientation, space, [x, y, width, height], expected) in cases {
+            let input = jpeg_with_orientation(orientation);
+            let mut config = ImageConfig {
+                auto_orient: true,
+                crop: Some(CropConfig {
+                    space,
+                    ..CropConfig::pixels(x, y, width, height)
+                }),
+                ..default_config()
+            };
+            resolve_crop_orientation(&mut config, &input);
+            let oriented = apply_auto_orient(img.clone(), &input);
+            let cropped = apply_crop(oriented, config.crop.as_ref().unwrap())
+                .unwrap()
+                .into_luma8();
+            assert_eq!(cropped.dimensions(), (1, 2), "orientation {}", orientation);
+            assert_eq!(
+                cropped.into_raw(),
+                expected,
+                "orientation {} rect {:?}",
+                orientation,
+                [x, y, width, height]
+            );
+        }
+    }
+
+    #[test]
+    fn grayscale_jpeg_is_single_channel_and_smaller() {
+        let color = DynamicImage::ImageRgb8(gradient(64, 64));
+        let color_data = encode(color.clone(), ImageFormat::Jpeg, &encode_options());
+        let gray_data = encode(color.grayscale(), ImageFormat::Jpeg, &encode_options());
+        let decoded = image::load_from_memory_with_format(&gray_data, ImageFormat::Jpeg).unwrap();
+        assert_eq!(decoded.color(), image::ColorType::L8);
+        assert!(gray_data.len() < color_data.len());
+    }
+
+    fn variance(img: &DynamicImage) -> f64 {
+        let values = img.to_luma8().into_raw();
+        let mean = values.iter().map(|&v| v as f64).sum::<f64>() / values.len() as f64;
+        values
+            .iter()
+            .map(|&v| (v as f64 - mean).powi(2))
+            .sum::<f64>()
+            / values.len() as f64
+    }
+
+    #[test]
+    fn sharpen_increases_variance_of_smooth_gradient() {
+        // 中间平滑过渡、两侧为纯色的渐变
+        let img = DynamicImage::ImageLuma8(GrayImage::from_fn(64, 8, |x, _| {
+            Luma([(x.clamp(16, 48) - 16) as u8 * 7])
+        }));
+        let sharpened = apply_sharpen(
+            img.clone(),
+            &SharpenConfig {
+                sigma: 2.0,
+                threshold: 0,
+            },
+        );
+        assert!(variance(&sharpened) > variance(&img));
+    }
+
+    #[test]
+    fn lzw_tiff_round_trip_is_lossless() {
+        let img = DynamicImage::ImageRgb8(gradient(13, 7));
+        let options = EncodeOptions {
+            tiff_compression: TiffCompression::Lzw,
+            ..encode_options()
+        };
+        let data = encode(img.clone(), ImageFormat::Tiff, &options);
+        let decoded = image::load_from_memory_with_format(&data, ImageFormat::Tiff).unwrap();
+        assert_eq!(decoded.to_rgb8(), img.to_rgb8());
+    }
+
+    #[test]
+    fn invert_preserves_alpha() {
+        let original = RgbaImage::from_fn(8, 8, |x, y| {
+            Rgba([(x * 30) as u8, (y * 30) as u8, 200, (x * y * 4) as u8])
+        });
+        let input = png(DynamicImage::ImageRgba8(original.clone()));
+        let config = ImageConfig {
+            invert: true,
+            ..default_config()
+        };
+        let output = process_with_config(&input, config, Vec::new()).unwrap();
+        let inverted = image::load_from_memory_with_format(&output.data, ImageFormat::Png)
+            .unwrap()
+            .to_rgba8();
+        for (pixel, original) in inverted.pixels().zip(original.pixels()) {
+            let [r, g, b, a] = original.0;
+            assert_eq!(pixel.0, [255 - r, 255 - g, 255 - b, a]);
+        }
+    }
+
+    #[test]
+    fn saturation_of_pure_red() {
+        let red = DynamicImage::ImageRgba8(RgbaImage::from_pixel(1, 1, Rgba([255, 0, 0, 128])));
+        let pixel = |saturation| {
+            *apply_saturation(red.clone(), saturation)
+                .as_rgba8()
+                .unwrap()
+                .get_pixel(0, 0)
+        };
+        assert_eq!(pixel(1.0), Rgba([255, 0, 0, 128]));
+        assert_eq!(pixel(2.0), Rgba([255, 0, 0, 128]));
+        assert_eq!(pixel(0.5), Rgba([191, 64, 64, 128]));
+        assert_eq!(pixel(0.0), Rgba([128, 128, 128, 128]));
+    }
+
+    fn watermark(content: Vec<u8>, position: [i64; 4]) -> WatermarkConfig {
+        WatermarkConfig {
+            content,
+            position,
+            opacity: 1.0,
+            use_watermark_alpha: false,
+            tile: false,
+            anchor: None,
+            margin: 0,
+            rotation: None,
+            scale_percent: None,
+            clip: false,
+            blend_mode: BlendMode::Normal,
+            fade: None,
+            filter: FilterType::Lanczos3,
+        }
+    }
+
+    fn png(img: DynamicImage) -> Vec<u8> {
+        encode(img, ImageFormat::Png, &encode_options())
+    }
+
+    #[test]
+    fn watermark_reuses_rgba_buffer() {
+        // 4000×3000 的 RGBA8 主图原地合成，不复制像素缓冲
+        let img = DynamicImage::ImageRgba8(RgbaImage::new(4000, 3000));
+        let buffer = img.as_bytes().as_ptr();
+        let logo = png(DynamicImage::ImageRgb8(gradient(64, 32)));
+        let watermarked = apply_watermark(img, &watermark(logo, [100, 100, 64, 32])).unwrap();
+        assert_eq!(watermarked.as_bytes().as_ptr(), buffer);
+        assert_ne!(watermarked.as_rgba8().unwrap().get_pixel(163, 131)[0], 0);
+    }
+
+    #[test]
+    fn decode_errors_keep_their_kind() {
+        use image::error::{LimitError, LimitErrorKind};
+        let limits =
+            image::ImageError::Limits(LimitError::from_kind(LimitErrorKind::DimensionError));
+        assert!(matches!(
+            CprError::decode("Failed to decode input image", limits),
+            CprError::InputTooLarge(_)
+        ));
+        let corrupt = decode_image(b"\x89PNG\r\n\x1a\nnot a png", ImageFormat::Png);
+        assert!(matches!(corrupt, Err(CprError::DecodeFailed(_))));
+        let watermark = apply_watermark(
+            DynamicImage::ImageRgb8(gradient(8, 8)),
+            &watermark(b"GIF89a".to_vec(), [0, 0, 4, 4]),
+        );
+        assert!(matches!(watermark, Err(CprError::DecodeFailed(_))));
+    }
+
+    #[test]
+    fn lossless_webp_keeps_text_crisp() {
+        // 1px 宽的黑白笔画，近似小字号文字的边缘
+        let text = DynamicImage::ImageRgba8(RgbaImage::from_fn(48, 16, |x, y| {
+            if (x % 3 == 0 && y > 2 && y < 13) || y == 8 {
+                Rgba([0, 0, 0, 255])
+            } else {
+                Rgba([255, 255, 255, 255])
+            }
+        }));
+        let decode = |options: &EncodeOptions| {
+            let data = encode(text.clone(), ImageFormat::WebP, options);
+            image::load_from_memory_with_format(&data, ImageFormat::WebP)
+                .unwrap()
+                .to_rgba8()
+        };
+        let lossless = EncodeOptions {
+            lossless: Some(true),
+            quality: Some(50),
+            ..encode_options()
+        };
+        assert_eq!(decode(&lossless), text.to_rgba8());
+        let lossy = EncodeOptions {
+            lossless: Some(false),
+            ..lossless
+        };
+        #[cfg(feature = "libwebp")]
+        assert_ne!(decode(&lossy), text.to_rgba8());
+        // 未启用 libwebp 时有损 WebP 明确报错，而不是退回无损
+        #[cfg(not(feature = "libwebp"))]
+        assert!(matches!(
+            encode_image(text, ImageFormat::WebP, &lossy, Vec::new()),
+            Err(CprError::UnsupportedOutputFormat(_))
+        ));
+    }
+
+    #[test]
+    fn oversized_crop_is_a_clean_error() {
+        let img = DynamicImage::ImageRgb8(gradient(10, 10));
+        for crop in [
+            CropConfig::pixels(1, 0, u32::MAX, 1),
+            CropConfig::pixels(u32::MAX, 0, 1, 1),
+            CropConfig::pixels(0, u32::MAX, 1, u32::MAX),
+        ] {
+            assert!(matches!(
+                apply_crop(img.clone(), &crop),
+                Err(CprError::CropOutOfBounds(_))
+            ));
+        }
+        assert!(apply_crop(img, &CropConfig::pixels(0, 0, 10, 10)).is_ok());
+    }
+
+    #[test]
+    fn oversized_watermark_is_a_clean_error() {
+        let logo = png(DynamicImage::ImageRgb8(gradient(4, 4)));
+        let max = u32::MAX as i64;
+        for position in [[1, 0, max, 4], [max, 0, 4, 4], [0, max, 4, max]] {
+            let result = apply_watermark(
+                DynamicImage::ImageRgb8(gradient(10, 10)),
+                &watermark(logo.clone(), position),
+            );
+            assert!(
+                matches!(result, Err(CprError::WatermarkOutOfBounds(_))),
+                "position {:?}",
+                position
+            );
+        }
+    }
+
+    #[test]
+    fn quality_outside_1_to_100_is_rejected() {
+        for quality in [0.0, 255.0, 100.5, f64::NAN] {
+            assert!(matches!(
+                validate_quality(Some(quality)),
+                Err(CprError::InvalidConfig(_))
+            ));
+        }
+        assert_eq!(validate_quality(Some(1.0)).unwrap(), Some(1));
+        assert_eq!(validate_quality(Some(100.0)).unwrap(), Some(100));
+        assert_eq!(validate_quality(None).unwrap(), None);
+    }
+
+    #[test]
+    fn non_integer_rotate_is_rejected() {
+        for rotate in [90.5, 45.0, 0.0, f64::NAN] {
+            assert!(matches!(
+                validate_rotate(Some(rotate)),
+                Err(CprError::InvalidConfig(_))
+            ));
+        }
+        assert_eq!(validate_rotate(Some(270.0)).unwrap(), Some(270));
+        assert_eq!(validate_rotate(None).unwrap(), None);
+    }
+
+    #[test]
+    fn sixteen_bit_gray_png_survives_crop() {
+        let gray = image::ImageBuffer::<Luma<u16>, Vec<u16>>::from_fn(16, 8, |x, y| {
+            Luma([(x * 4099 + y * 37) as u16])
+        });
+        let input = png(DynamicImage::ImageLuma16(gray.clone()));
+        let decoded = decode_image(&input, ImageFormat::Png).unwrap();
+        let cropped = apply_crop(decoded, &CropConfig::pixels(2, 1, 10, 6)).unwrap();
+        let output = png(cropped);
+        let round_trip = decode_image(&output, ImageFormat::Png).unwrap();
+        let expected = image::imageops::crop_imm(&gray, 2, 1, 10, 6).to_image();
+        assert_eq!(round_trip.as_luma16(), Some(&expected));
+    }
+
+    #[test]
+    fn qoi_round_trip_is_lossless() {
+        let rgba = DynamicImage::ImageRgba8(RgbaImage::from_fn(11, 9, |x, y| {
+            Rgba([
+                (x * 23) as u8,
+                (y * 29) as u8,
+                (x * y) as u8,
+                (255 - x * 9) as u8,
+            ])
+        }));
+        let rgb = DynamicImage::ImageRgb8(gradient(11, 9));
+        for img in [rgba, rgb] {
+            let data = encode(img.clone(), ImageFormat::Qoi, &encode_options());
+            assert_eq!(image::guess_format(&data).unwrap(), ImageFormat::Qoi);
+            let decoded = decode_image(&data, ImageFormat::Qoi).unwrap();
+            assert_eq!(decoded.to_rgba8(), img.to_rgba8());
+        }
+    }
+
+    #[test]
+    fn semi_transparent_png_flattens_onto_red() {
+        // 左半边为半透明蓝色，右半边全透明
+        let logo = RgbaImage::from_fn(16, 16, |x, _| match x {
+            0..8 => Rgba([0, 0, 255, 128]),
+            _ => Rgba([0, 0, 0, 0]),
+        });
+        let input = png(DynamicImage::ImageRgba8(logo));
+        let options = EncodeOptions {
+            quality: Some(100),
+            flatten_background: Rgba([255, 0, 0, 255]),
+            ..encode_options()
+        };
+        let data = encode(
+            decode_image(&input, ImageFormat::Png).unwrap(),
+            ImageFormat::Jpeg,
+            &options,
+        );
+        let output = decode_image(&data, ImageFormat::Jpeg).unwrap().to_rgb8();
+        let near = |pixel: &Rgb<u8>, expected: [u8; 3]| {
+            pixel
+                .0
+                .iter()
+                .zip(expected)
+                .all(|(&a, b)| a.abs_diff(b) <= 8)
+        };
+        assert!(
+            near(output.get_pixel(3, 8), [127, 0, 128]),
+            "{:?}",
+            output.get_pixel(3, 8)
+        );
+        assert!(
+            near(output.get_pixel(12, 8), [255, 0, 0]),
+            "{:?}",
+            output.get_pixel(12, 8)
+        );
+    }
+
+    // 基准测试：cargo test --release -- --ignored --nocapture
+    fn time<T>(f: impl FnOnce() -> T) -> (T, std::time::Duration) {
+        let start = std::time::Instant::now();
+        let value = f();
+        (value, start.elapsed())
+    }
+
+    #[test]
+    #[ignore]
+    fn bench_jpeg_scaled_decode() {
+        let photo = DynamicImage::ImageRgb8(gradient(6000, 4000));
+        let input = encode(photo, ImageFormat::Jpeg, &encode_options());
+        let config = ImageConfig {
+            size: Some(size_config(Some(400), None, None)),
+            ..default_config()
+        };
+        let size = config.size.as_ref().unwrap();
+        let (full, full_time) = time(|| {
+            let img = decode_image(&input, ImageFormat::Jpeg).unwrap();
+            apply_resize(&img, size, false, false).unwrap()
+        });
+        let (scaled, scaled_time) = time(|| {
+            let img = decode_jpeg_scaled(&input, &config).unwrap();
+            apply_resize(&img, size, false, false).unwrap()
+        });
+        assert_eq!(
+            (full.width(), full.height()),
+            (scaled.width(), scaled.height())
+        );
+        println!(
+            "6000x4000 JPEG to 400px: full decode {:?}, scaled decode {:?}",
+            full_time, scaled_time
+        );
+    }
+
+    #[test]
+    fn cmyk_jpeg_decodes_to_plausible_rgb() {
+        // 左半青色、右半纯黑
+        let (width, height) = (16u16, 16u16);
+        let cmyk: Vec<u8> = (0..width as usize * height as usize)
+            .flat_map(|i| match i % width as usize {
+                x if x < 8 => [255, 0, 0, 0],
+                _ => [0, 0, 0, 255],
+            })
+            .collect();
+        let mut input = Vec::new();
+        jpeg_encoder::Encoder::new(&mut input, 100)
+            .encode(&cmyk, width, height, jpeg_encoder::ColorType::Cmyk)
+            .unwrap();
+        let mut decoder = jpeg_decoder::Decoder::new(Cursor::new(&input));
+        decoder.read_info().unwrap();
+        assert_eq!(
+            decoder.info().unwrap().pixel_format,
+            jpeg_decoder::PixelFormat::CMYK32
+        );
+
+        let img = decode_image(&input, ImageFormat::Jpeg).unwrap().to_rgb8();
+        let [r, g, b] = img.get_pixel(2, 8).0;
+        assert!(
+            r < 40 && g > 215 && b > 215,
+            "cyan decoded as {:?}",
+            [r, g, b]
+        );
+        let [r, g, b] = img.get_pixel(13, 8).0;
+        assert!(
+            r < 40 && g < 40 && b < 40,
+            "black decoded as {:?}",
+            [r, g, b]
+        );
+    }
+
+    #[test]
+    fn jpeg_scale_is_applied_once() {
+        let input = encode(
+            DynamicImage::ImageRgb8(gradient(640, 480)),
+            ImageFormat::Jpeg,
+            &encode_options(),
+        );
+        let config = ImageConfig {
+            size: Some(size_config(None, None, Some(0.5))),
+            ..default_config()
+        };
+        assert!(decode_jpeg_scaled(&input, &config).is_none());
+        let img = decode_image(&input, ImageFormat::Jpeg).unwrap();
+        let img = transform_image(img, &input, &config).unwrap();
+        assert_eq!((img.width(), img.height()), (320, 240));
+    }
+
+    #[test]
+    fn jpeg_crop_aspect_is_not_scaled_at_decode() {
+        // 1:10 的窄条只保留原图很小一部分宽度，按整图估算缩放比会让裁剪后的分辨率不足
+        let input = encode(
+            DynamicImage::ImageRgb8(gradient(800, 600)),
+            ImageFormat::Jpeg,
+            &encode_options(),
+        );
+        let config = ImageConfig {
+            crop_aspect: Some(CropAspectConfig {
+                ratio_w: 1,
+                ratio_h: 10,
+            }),
+            size: Some(size_config(Some(48), None, None)),
+            ..default_config()
+        };
+        assert!(decode_jpeg_scaled(&input, &config).is_none());
+        let img = decode_input(&input, ImageFormat::Jpeg, &config).unwrap();
+        assert_eq!((img.width(), img.height()), (800, 600));
+        let img = transform_image(img, &input, &config).unwrap();
+        assert_eq!((img.width(), img.height()), (48, 480));
+    }
+
+    #[test]
+    fn palette_png_is_smaller_and_exact_for_few_colors() {
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_fn(256, 256, |x, y| {
+            let i = ((x / 32 + y / 32) % 8) as u8;
+            Rgba([i * 30, 255 - i * 30, i * 15, 255])
+        }));
+        let truecolor = encode(img.clone(), ImageFormat::Png, &encode_options());
+        let options = EncodeOptions {
+            png_palette: true,
+            ..encode_options()
+        };
+        let palette = encode(img.clone(), ImageFormat::Png, &options);
+        assert!(palette.len() < truecolor.len());
+
+        let decoded = decode_image(&palette, ImageFormat::Png).unwrap();
+        assert_eq!(decoded.to_rgba8(), img.to_rgba8());
+    }
+
+    #[test]
+    fn dithering_increases_adjacent_variation() {
+        let img = DynamicImage::ImageRgb8(gradient(256, 64)).into_rgba8();
+        let changes = |indices: &[u8]| {
+            indices
+                .chunks_exact(256)
+                .map(|row| row.windows(2).filter(|pair| pair[0] != pair[1]).count())
+                .sum::<usize>()
+        };
+        let (_, plain) = quantize_palette(&img, 4, false);
+        let (_, dithered) = quantize_palette(&img, 4, true);
+        assert!(changes(&dithered) > changes(&plain) * 2);
+    }
+
+    #[test]
+    fn sobel_response_is_concentrated_on_the_line() {
+        let img = DynamicImage::ImageLuma8(GrayImage::from_fn(64, 64, |x, _| {
+            Luma([if x == 32 { 255 } else { 0 }])
+        }));
+        let edges = apply_edges(img).into_luma8();
+        let (near, total) =
+            edges
+                .enumerate_pixels()
+                .fold((0u64, 0u64), |(near, total), (x, _, p)| {
+                    let value = p[0] as u64;
+                    (
+                        near + if x.abs_diff(32) <= 1 { value } else { 0 },
+                        total + value,
+                    )
+                });
+        assert!(total > 0);
+        assert!(
+            near * 100 >= total * 95,
+            "{} of {} on the line",
+            near,
+            total
+        );
+        assert_eq!(edges.get_pixel(10, 32)[0], 0);
+    }
 
-    // 编码并返回
-    encode_image(img, output_format, config.quality)
+    #[test]
+    fn multiply_with_white_leaves_image_unchanged() {
+        let img = DynamicImage::ImageRgb8(gradient(64, 48));
+        let white = png(DynamicImage::ImageRgb8(RgbImage::from_pixel(
+            64,
+            48,
+            Rgb([255, 255, 255]),
+        )));
+        let config = WatermarkConfig {
+            blend_mode: BlendMode::Multiply,
+            ..watermark(white, [0, 0, 64, 48])
+        };
+        let blended = apply_watermark(img.clone(), &config).unwrap();
+        assert_eq!(blended.to_rgb8(), img.to_rgb8());
+    }
+
+    #[test]
+    fn png_downscales_to_target_size() {
+        // 噪声图几乎无法压缩，只能缩小尺寸满足字节预算
+        let mut state = 1u32;
+        let noise = DynamicImage::ImageRgb8(RgbImage::from_fn(128, 128, |_, _| {
+            // xorshift32 伪随机数
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            Rgb([(state >> 24) as u8, (state >> 16) as u8, (state >> 8) as u8])
+        }));
+        let input = png(noise);
+        // 第一步 (109 × 109) 仍超出预算，第二步满足
+        let target = input.len() * 3 / 5;
+        let mut config = default_config();
+        config.encode.target_size_bytes = Some(target);
+        config.encode.target_size_downscale = true;
+        let output = process_with_config(&input, config, Vec::new()).unwrap();
+        assert!(output.data.len() <= target);
+
+        let decoded = image::load_from_memory_with_format(&output.data, ImageFormat::Png).unwrap();
+        assert_eq!(
+            (output.width, output.height),
+            (decoded.width(), decoded.height())
+        );
+        // 第二步从原图缩放到 128 × 0.85² ≈ 92，逐步缩放会得到 round(109 × 0.85) = 93
+        assert_eq!((output.width, output.height), (92, 92));
+    }
+
+    #[test]
+    #[ignore]
+    fn bench_linear_resize() {
+        // 1px 黑白条纹，缩小后线性光均值约 188，sRGB 均值约 127
+        let img = DynamicImage::ImageRgb8(RgbImage::from_fn(1024, 1024, |x, _| {
+            Rgb([if x % 2 == 0 { 0 } else { 255 }; 3])
+        }));
+        let size = size_config(Some(256), Some(256), None);
+        let (srgb, srgb_time) =
+            time(|| apply_resize_rgba16(&img, &size, false, false, false, true).unwrap());
+        let (linear, linear_time) =
+            time(|| apply_resize_rgba16(&img, &size, false, false, true, true).unwrap());
+        let mean = |img: &DynamicImage| {
+            let rgb = img.to_rgb8();
+            rgb.as_raw().iter().map(|&v| v as u64).sum::<u64>() / rgb.as_raw().len() as u64
+        };
+        assert!(mean(&linear) > mean(&srgb) + 40);
+        println!(
+            "1024x1024 to 256x256 Lanczos3: sRGB {:?} (mean {}), linear {:?} (mean {})",
+            srgb_time,
+            mean(&srgb),
+            linear_time,
+            mean(&linear)
+        );
+    }
+
+    #[test]
+    fn inputs_without_metadata_pass_through() {
+        let jpeg = encode(
+            DynamicImage::ImageRgb8(gradient(64, 48)),
+            ImageFormat::Jpeg,
+            &encode_options(),
+        );
+        let output = process_with_config(&jpeg, default_config(), Vec::new()).unwrap();
+        assert_eq!(output.data, jpeg);
+
+        let png = png(DynamicImage::ImageRgb8(gradient(64, 48)));
+        let output = process_with_config(&png, default_config(), Vec::new()).unwrap();
+        assert_eq!(output.data, png);
+    }
+
+    #[test]
+    fn inputs_with_metadata_are_stripped() {
+        let jpeg = jpeg_with_orientation(1);
+        assert!(jpeg_has_metadata(&jpeg).unwrap());
+        let output = process_with_config(&jpeg, default_config(), Vec::new()).unwrap();
+        assert_eq!(jpeg_has_metadata(&output.data), Some(false));
+
+        let png = insert_png_chunk(
+            png(DynamicImage::ImageRgb8(gradient(64, 48))),
+            b"tEXt",
+            b"Comment\0hello",
+        )
+        .unwrap();
+        let output = process_with_config(&png, default_config(), Vec::new()).unwrap();
+        assert_ne!(output.data, png);
+        assert_eq!(png_has_metadata(&output.data), Some(false));
+    }
+
+    #[test]
+    fn premultiplied_resize_keeps_edges_bright() {
+        // 透明黑底上的不透明白色方块，缩小后半透明的边缘应仍为白色
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_fn(64, 64, |x, y| {
+            match (16..48).contains(&x) && (16..48).contains(&y) {
+                true => Rgba([255, 255, 255, 255]),
+                false => Rgba([0, 0, 0, 0]),
+            }
+        }));
+        let size = SizeConfig {
+            filter: Some("triangle".into()),
+            ..size_config(Some(24), Some(24), None)
+        };
+        let darkest = |premultiply: bool| {
+            let resized =
+                apply_resize_rgba16(&img, &size, false, false, false, premultiply).unwrap();
+            resized
+                .to_rgba8()
+                .pixels()
+                .filter(|pixel| pixel[3] > 0)
+                .map(|pixel| pixel[0])
+                .min()
+                .unwrap()
+        };
+        assert!(darkest(true) >= 250);
+        assert!(darkest(false) < 200);
+    }
+
+    #[test]
+    fn srcset_applies_watermark_once_or_per_size() {
+        let input = png(DynamicImage::ImageRgb8(gradient(64, 48)));
+        let logo = png(DynamicImage::ImageRgb8(RgbImage::from_pixel(
+            16,
+            16,
+            Rgb([255, 0, 0]),
+        )));
+        let config = || ImageConfig {
+            watermarks: vec![watermark(logo.clone(), [0, 0, 16, 16])],
+            ..default_config()
+        };
+        let red_at = |once, x, y| {
+            let mut outputs = Vec::new();
+            process_srcset(&input, config(), &[32, 64], once, |processed| {
+                outputs.push((processed.width, processed.data.clone()));
+                Ok(())
+            })
+            .unwrap();
+            assert_eq!(
+                outputs.iter().map(|(width, _)| *width).collect::<Vec<_>>(),
+                [32, 64]
+            );
+            let img = image::load_from_memory(&outputs[0].1).unwrap().to_rgb8();
+            let [r, g, b] = img.get_pixel(x, y).0;
+            r > 200 && g < 50 && b < 50
+        };
+        // 缩放前合成一次时水印随图像缩小为 8×8，逐尺寸合成时保持 16×16
+        assert!(red_at(true, 4, 4));
+        assert!(!red_at(true, 12, 12));
+        assert!(red_at(false, 12, 12));
+
+        let config = ImageConfig {
+            size: Some(size_config(Some(100), Some(50), None)),
+            ..default_config()
+        };
+        assert!(matches!(
+            process_srcset(&input, config, &[32], false, |_| Ok(())),
+            Err(CprError::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn grayscale_pipeline_keeps_watermarked_jpeg_single_channel() {
+        // 水印合成会把 grayscale 后的单通道图像转为 RGBA；灰色水印下内容仍为灰度
+        let input = encode(
+            DynamicImage::ImageRgb8(gradient(64, 48)),
+            ImageFormat::Jpeg,
+            &encode_options(),
+        );
+        let logo = png(DynamicImage::ImageRgb8(RgbImage::from_pixel(
+            8,
+            8,
+            Rgb([128, 128, 128]),
+        )));
+        let config = ImageConfig {
+            grayscale: true,
+            watermarks: vec![watermark(logo, [0, 0, 8, 8])],
+            ..default_config()
+        };
+        let output = process_with_config(&input, config, Vec::new()).unwrap();
+        let decoded = image::load_from_memory(&output.data).unwrap();
+        assert_eq!(decoded.color(), image::ColorType::L8);
+
+        // 未执行 grayscale 时不扫描像素，中性色的 RGBA 图像仍按三通道编码
+        let neutral = DynamicImage::ImageRgba8(
+            DynamicImage::ImageRgb8(gradient(64, 48))
+                .grayscale()
+                .to_rgba8(),
+        );
+        let data = encode(neutral, ImageFormat::Jpeg, &encode_options());
+        let decoded = image::load_from_memory(&data).unwrap();
+        assert_eq!(decoded.color(), image::ColorType::Rgb8);
+    }
 }